@@ -0,0 +1,141 @@
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use anyhow::{Context, Result};
+use log::*;
+use subprocess::{Exec, Redirection};
+
+/// The chooser that is used if none is configured and opener is not running in a terminal
+const DEFAULT_CHOOSER: &str = "rofi -dmenu -i -p open";
+
+/// The value of the `chooser` key in the config that selects the built in prompt
+pub const PROMPT: &str = "prompt";
+
+/// Something that lets the user pick one command out of many
+#[derive(Debug, Clone, PartialEq)]
+pub enum Chooser {
+    /// A program like rofi, dmenu, or fzf. The candidates are written to its stdin, one per line,
+    /// and the selection is read from its stdout.
+    Command(String),
+
+    /// A numbered list printed to the terminal that the user picks a number from
+    Prompt,
+}
+
+impl Chooser {
+    /// Creates a chooser from the `chooser` key in the config. If nothing is configured, the
+    /// built in prompt is used in a terminal and rofi is used otherwise.
+    pub fn new(command: Option<String>) -> Chooser {
+        match command {
+            Some(command) if command == PROMPT => Chooser::Prompt,
+            Some(command) => Chooser::Command(command),
+            None if io::stdin().is_terminal() && io::stderr().is_terminal() => Chooser::Prompt,
+            None => Chooser::Command(DEFAULT_CHOOSER.to_string()),
+        }
+    }
+
+    /// Lets the user choose one of the candidates. Returns None if the user cancelled.
+    pub fn choose(&self, candidates: &[String]) -> Result<Option<String>> {
+        match self {
+            Chooser::Command(command) => choose_with_command(command, candidates),
+            Chooser::Prompt => choose_with_prompt(candidates),
+        }
+    }
+}
+
+/// Choose the command with an external program like rofi. The user is allowed to type in a command
+/// that is not one of the candidates if the program supports it.
+fn choose_with_command(command: &str, candidates: &[String]) -> Result<Option<String>> {
+    debug!("Choosing with {}", command);
+    let capture = Exec::shell(command)
+        .stdin(candidates.join("\n").as_str())
+        .stdout(Redirection::Pipe)
+        .capture()
+        .context(format!("Failed to run the chooser {}", command))?;
+
+    // rofi, dmenu and fzf all exit with a non zero status when the user cancels
+    if !capture.success() {
+        return Ok(None);
+    }
+
+    let choice = capture.stdout_str().trim().to_string();
+    if choice.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(choice))
+    }
+}
+
+/// Choose the command by printing a numbered list to stderr and reading the number from stdin
+fn choose_with_prompt(candidates: &[String]) -> Result<Option<String>> {
+    let stderr = io::stderr();
+    let mut stderr = stderr.lock();
+    for (i, candidate) in candidates.iter().enumerate() {
+        writeln!(stderr, "{}) {}", i + 1, candidate)?;
+    }
+
+    let stdin = io::stdin();
+    let mut line = String::new();
+    loop {
+        write!(stderr, "Choose a command [1-{}]: ", candidates.len())?;
+        stderr.flush()?;
+
+        line.clear();
+        // EOF means the user cancelled
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+
+        match line.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= candidates.len() => return Ok(Some(candidates[n - 1].clone())),
+            _ => writeln!(stderr, "{} is not a valid choice", line)?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates() -> Vec<String> {
+        vec![
+            "sxiv".to_string(),
+            "feh --fs".to_string(),
+            "gimp".to_string(),
+        ]
+    }
+
+    #[test]
+    fn command_chooser_returns_what_the_program_prints() {
+        let chooser = Chooser::new(Some("head -n2 | tail -n1".to_string()));
+        let choice = chooser.choose(&candidates()).unwrap();
+        assert_eq!(choice.as_deref(), Some("feh --fs"));
+    }
+
+    #[test]
+    fn command_chooser_can_return_a_command_of_its_own() {
+        let choice = choose_with_command("cat >/dev/null; echo 'mpv %f'", &candidates()).unwrap();
+        assert_eq!(choice.as_deref(), Some("mpv %f"));
+    }
+
+    #[test]
+    fn failing_command_chooser_cancels() {
+        let choice = choose_with_command("head -n1; exit 1", &candidates()).unwrap();
+        assert_eq!(choice, None);
+    }
+
+    #[test]
+    fn empty_choice_cancels() {
+        let choice = choose_with_command("cat >/dev/null", &candidates()).unwrap();
+        assert_eq!(choice, None);
+    }
+
+    #[test]
+    fn prompt_is_selected_by_name() {
+        assert_eq!(Chooser::new(Some("prompt".to_string())), Chooser::Prompt);
+    }
+}
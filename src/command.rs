@@ -0,0 +1,1059 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::*;
+use mime::Mime;
+use serde_derive::Deserialize;
+use subprocess::Exec;
+
+use crate::desktop::DesktopEntry;
+use crate::target::is_uri;
+
+/// The character that starts a placeholder in a command string
+const PLACEHOLDER: char = '%';
+
+/// The value of `cwd` that runs a command in the directory of the file
+const FILE_DIR: &str = "file_dir";
+
+/// The terminal that commands are run in if `TERMINAL` is not set
+const DEFAULT_TERMINAL: &str = "xterm";
+
+/// How long opener waits for a detached command to fail before leaving it running
+const GRACE_PERIOD: Duration = Duration::from_millis(300);
+
+/// Settings from the top of the config that apply to every command
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunOptions {
+    /// Whether commands are detached unless their rule says otherwise
+    pub detach: bool,
+    /// The file that the output of detached commands is appended to, None to discard it
+    pub detach_log: Option<PathBuf>,
+    /// The shell command that runs a command in a new terminal, like `alacritty -e`. The command
+    /// is added after it. `$TERMINAL -e` is used if None.
+    pub terminal_command: Option<String>,
+    /// Whether the commands preview files. Previews write to stdout, so they are never detached.
+    pub preview: bool,
+}
+
+impl RunOptions {
+    /// Whether a command is detached. `detach` is what its rule says, None to use the config. A
+    /// command that runs in the terminal opener is running in is not detached either.
+    pub fn detaches(&self, detach: Option<bool>, terminal: bool) -> bool {
+        !self.preview && detach.unwrap_or(self.detach) && !on_current_terminal(terminal)
+    }
+}
+
+/// What a rule runs to open a file
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(
+    untagged,
+    expecting = "a command should be a string or a table like { cmd = \"...\" } or { desktop = \"...\" }"
+)]
+pub enum Command {
+    /// A shell command with placeholders, like `sxiv -a %f`
+    Shell(String),
+    /// An application from its desktop file, like `{ desktop = "org.pwmt.zathura.desktop" }`
+    Desktop { desktop: String },
+    /// A command with options, like `{ cmd = "mpv", args = ["--fs"] }`
+    Structured(StructuredCommand),
+}
+
+/// A command with options for how it is run
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StructuredCommand {
+    /// The program, or a shell command if `shell` is true
+    pub cmd: String,
+    /// The arguments, which can have placeholders. If none of them do, the paths are added after
+    /// them.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Whether the command has to be run in a terminal
+    #[serde(default)]
+    pub terminal: bool,
+    /// Whether opener exits without waiting for the command, the `detach` setting if not set
+    pub detach: Option<bool>,
+    /// Environment variables to set for the command
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// The directory to run the command in, `file_dir` for the directory of the file
+    pub cwd: Option<String>,
+    /// Whether `cmd` is run with sh, so it can use pipes and variables
+    #[serde(default = "default_shell")]
+    pub shell: bool,
+}
+
+/// Commands are run with the shell unless they say otherwise
+fn default_shell() -> bool {
+    true
+}
+
+impl Command {
+    /// Parses a command given on the command line, which is a shell command unless it is an
+    /// inline table like `{ cmd = "mpv", detach = true }`
+    pub fn parse(s: &str) -> Result<Command> {
+        if !s.trim_start().starts_with('{') {
+            return Ok(Command::Shell(s.to_string()));
+        }
+        Command::from_toml(s)
+    }
+
+    /// Parses a command from a toml value like it is written in the config
+    pub fn from_toml(value: &str) -> Result<Command> {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            command: Command,
+        }
+        let wrapper: Wrapper = toml::from_str(&format!("command = {}", value))
+            .context(format!("The command {} is not valid", value.trim()))?;
+        Ok(wrapper.command)
+    }
+
+    /// The command as a toml value like it is written in the config
+    pub fn to_toml(&self) -> String {
+        match self {
+            Command::Shell(cmd) => toml_string(cmd),
+            command => command.to_string(),
+        }
+    }
+
+    /// Runs the command with the paths and returns an error if it failed. The programs it
+    /// detached are returned so that their grace period can be checked with `check_detached`.
+    pub fn run(&self, paths: &[&Path], mime: &Mime, options: &RunOptions) -> Result<Vec<Detached>> {
+        match self {
+            Command::Shell(cmd) => {
+                let cmd = expand_command(cmd, paths, mime)?;
+                debug!("Running command: {}", cmd.to_string_lossy());
+                let args = ["sh".into(), "-c".into(), cmd];
+                let detach = options.detaches(None, false);
+                let failure = format!(
+                    "The child command {} with path {} failed",
+                    args[2].to_string_lossy(),
+                    display_paths(paths)
+                );
+                let detached = execute(&args, &BTreeMap::new(), None, detach, options)?;
+                Ok(detached.check(failure)?.into_iter().collect())
+            }
+            Command::Desktop { desktop } => DesktopEntry::find(desktop)?.launch(paths, options),
+            Command::Structured(command) => command.run(paths, mime, options),
+        }
+    }
+
+    /// Whether the command can open many files at once. A command that only has placeholders for
+    /// one file, like `%f`, has to be run once for each file.
+    pub fn takes_many(&self) -> bool {
+        match self {
+            Command::Shell(cmd) => takes_many(&[cmd]),
+            // the application is run once for each file by itself if it has to be
+            Command::Desktop { desktop } => DesktopEntry::find(desktop)
+                .and_then(|entry| entry.takes_many())
+                .unwrap_or(true),
+            Command::Structured(command) => {
+                let mut words: Vec<&String> = command.args.iter().collect();
+                if command.shell {
+                    words.push(&command.cmd);
+                }
+                takes_many(&words)
+            }
+        }
+    }
+
+    /// What would be run for the paths, without running it
+    pub fn describe(&self, paths: &[&Path], mime: &Mime, options: &RunOptions) -> Result<String> {
+        let (described, detach) = match self {
+            Command::Shell(cmd) => {
+                let cmd = expand_command(cmd, paths, mime)?;
+                (cmd.to_string_lossy().into_owned(), options.detaches(None, false))
+            }
+            Command::Desktop { desktop } => {
+                let entry = DesktopEntry::find(desktop)?;
+                entry.check_installed()?;
+                let invocations = entry.command_lines(paths, options)?;
+                let described = invocations
+                    .iter()
+                    .map(|args| quote_args(args).to_string_lossy().into_owned())
+                    .collect::<Vec<_>>()
+                    .join("; ");
+                (described, options.detaches(None, entry.terminal))
+            }
+            Command::Structured(command) => {
+                (command.describe(paths, mime, options)?, command.detaches(options))
+            }
+        };
+        if detach {
+            Ok(format!("{} (detached)", described))
+        } else {
+            Ok(described)
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Shell(cmd) => write!(f, "{}", cmd),
+            Command::Desktop { desktop } => write!(f, "{{ desktop = {} }}", toml_string(desktop)),
+            Command::Structured(command) => write!(f, "{}", command),
+        }
+    }
+}
+
+impl StructuredCommand {
+    /// The program and its arguments with the placeholders expanded
+    fn command_line(
+        &self,
+        paths: &[&Path],
+        mime: &Mime,
+        options: &RunOptions,
+    ) -> Result<Vec<OsString>> {
+        let args = if self.shell {
+            // the arguments are quoted and added to the shell command, so the placeholders are
+            // expanded and quoted by the shell expansion
+            let mut line = self.cmd.clone();
+            for arg in &self.args {
+                line.push(' ');
+                line.push_str(&quote_arg(arg));
+            }
+            vec!["sh".into(), "-c".into(), expand_command(&line, paths, mime)?]
+        } else {
+            let mut args = vec![OsString::from(&self.cmd)];
+            args.extend(expand_args(&self.args, paths, mime)?);
+            args
+        };
+
+        Ok(if self.terminal { in_terminal(args, options) } else { args })
+    }
+
+    /// The directory the command is run in, None to use the current one
+    fn dir(&self, paths: &[&Path]) -> Option<PathBuf> {
+        let cwd = self.cwd.as_deref()?;
+        if cwd != FILE_DIR {
+            return Some(PathBuf::from(cwd));
+        }
+        let first = paths.first()?;
+        if is_uri(first) {
+            return None;
+        }
+        let dir = first.parent()?;
+        if dir.as_os_str().is_empty() {
+            None
+        } else {
+            Some(dir.to_path_buf())
+        }
+    }
+
+    /// Whether the command is detached, a command that runs in the terminal opener is running in
+    /// is never detached because it would lose the terminal
+    fn detaches(&self, options: &RunOptions) -> bool {
+        options.detaches(self.detach, self.terminal)
+    }
+
+    /// Runs the command and waits for it unless it is detached
+    fn run(&self, paths: &[&Path], mime: &Mime, options: &RunOptions) -> Result<Vec<Detached>> {
+        let dir = self.dir(paths);
+        let paths = self.relative_to(&dir, paths)?;
+        let paths: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+        let args = self.command_line(&paths, mime, options)?;
+        debug!("Running {:?}", args);
+
+        let failure = format!(
+            "The child command {} with path {} failed",
+            quote_args(&args).to_string_lossy(),
+            display_paths(&paths)
+        );
+        let launch = execute(&args, &self.env, dir.as_deref(), self.detaches(options), options)?;
+        Ok(launch.check(failure)?.into_iter().collect())
+    }
+
+    /// The paths as the command sees them, they are made absolute when it runs in another
+    /// directory so that they still point at the same files
+    fn relative_to(&self, dir: &Option<PathBuf>, paths: &[&Path]) -> Result<Vec<PathBuf>> {
+        paths
+            .iter()
+            .map(|path| match dir {
+                Some(_) if !is_uri(path) => std::path::absolute(path)
+                    .context(format!("Failed to find the absolute path of {}", path.display())),
+                _ => Ok(path.to_path_buf()),
+            })
+            .collect()
+    }
+
+    /// What would be run, with the environment variables and the directory
+    fn describe(&self, paths: &[&Path], mime: &Mime, options: &RunOptions) -> Result<String> {
+        let dir = self.dir(paths);
+        let paths = self.relative_to(&dir, paths)?;
+        let paths: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+        let mut words: Vec<String> = self
+            .env
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, quote(value)))
+            .collect();
+        let command_line = quote_args(&self.command_line(&paths, mime, options)?);
+        words.push(command_line.to_string_lossy().into_owned());
+        let mut described = words.join(" ");
+        if let Some(dir) = dir {
+            described.push_str(&format!(" (in {})", dir.display()));
+        }
+        Ok(described)
+    }
+}
+
+impl fmt::Display for StructuredCommand {
+    /// Shows the command as an inline table with only the options that are not the default
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut fields = vec![format!("cmd = {}", toml_string(&self.cmd))];
+        if !self.args.is_empty() {
+            let args: Vec<String> = self.args.iter().map(|arg| toml_string(arg)).collect();
+            fields.push(format!("args = [{}]", args.join(", ")));
+        }
+        if self.terminal {
+            fields.push("terminal = true".to_string());
+        }
+        if let Some(detach) = self.detach {
+            fields.push(format!("detach = {}", detach));
+        }
+        if !self.env.is_empty() {
+            let env: Vec<String> = self
+                .env
+                .iter()
+                .map(|(name, value)| format!("{} = {}", toml_string(name), toml_string(value)))
+                .collect();
+            fields.push(format!("env = {{ {} }}", env.join(", ")));
+        }
+        if let Some(cwd) = &self.cwd {
+            fields.push(format!("cwd = {}", toml_string(cwd)));
+        }
+        if !self.shell {
+            fields.push("shell = false".to_string());
+        }
+        write!(f, "{{ {} }}", fields.join(", "))
+    }
+}
+
+/// A program that was run by `execute`
+pub enum Launch {
+    /// It was waited for and exited, successfully or not
+    Exited(bool),
+    /// It was detached and is still running
+    Detached(process::Child),
+}
+
+impl Launch {
+    /// Fails with `failure` if the program exited with an error. A detached program is returned
+    /// so that it can be given its grace period with `check_detached`.
+    pub fn check(self, failure: String) -> Result<Option<Detached>> {
+        match self {
+            Launch::Exited(true) => Ok(None),
+            Launch::Exited(false) => bail!("{}", failure),
+            Launch::Detached(child) => Ok(Some(Detached { child, failure })),
+        }
+    }
+}
+
+/// A detached program that has not had its grace period yet
+pub struct Detached {
+    child: process::Child,
+    /// Why the run failed if the program exits with an error during the grace period
+    failure: String,
+}
+
+/// Runs a program. A program that is not detached is waited for. A detached program is started
+/// in a new session with its output going to the log, and is given a short grace period by
+/// `check_detached`. If it exits with an error by then it failed, otherwise it is left running
+/// and outlives opener without being tied to its terminal.
+pub fn execute(
+    args: &[OsString],
+    env: &BTreeMap<String, String>,
+    dir: Option<&Path>,
+    detach: bool,
+    options: &RunOptions,
+) -> Result<Launch> {
+    let (program, rest) = args
+        .split_first()
+        .ok_or_else(|| anyhow!("There is no program to run"))?;
+    let name = program.to_string_lossy();
+
+    if !detach {
+        let mut exec = Exec::cmd(program).args(rest);
+        for (name, value) in env {
+            exec = exec.env(name, value);
+        }
+        if let Some(dir) = dir {
+            exec = exec.cwd(dir);
+        }
+        let exit_status = exec
+            .join()
+            .context(format!("Failed to run {}", name))?;
+        return Ok(Launch::Exited(exit_status.success()));
+    }
+
+    let mut command = process::Command::new(program);
+    command.args(rest).envs(env).stdin(Stdio::null());
+    if let Some(dir) = dir {
+        command.current_dir(dir);
+    }
+    match &options.detach_log {
+        Some(log) => {
+            let log = open_log(log)?;
+            command.stdout(log.try_clone()?).stderr(log);
+        }
+        None => {
+            command.stdout(Stdio::null()).stderr(Stdio::null());
+        }
+    }
+    // a new session has no controlling terminal, so closing the terminal opener was started from
+    // does not hang up the program. setsid is async-signal-safe, so it can run between fork and
+    // exec.
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+
+    // failing to exec the program is reported by spawn
+    let child = command
+        .spawn()
+        .context(format!("Failed to run {}", name))?;
+    debug!("Detached {} with pid {}", name, child.id());
+    Ok(Launch::Detached(child))
+}
+
+/// Gives the detached programs of each run one grace period together to fail, instead of one
+/// each, and returns for each run whether they all succeeded or were left running
+pub fn check_detached(runs: Vec<Vec<Detached>>) -> Vec<Result<()>> {
+    let mut results: Vec<Option<Result<()>>> = runs.iter().map(|_| None).collect();
+    let mut pending: Vec<(usize, Vec<Detached>)> = runs.into_iter().enumerate().collect();
+    let start = Instant::now();
+    loop {
+        let mut still_running = Vec::new();
+        for (index, children) in pending {
+            match still_running_children(children) {
+                Ok(children) if children.is_empty() => results[index] = Some(Ok(())),
+                Ok(children) => still_running.push((index, children)),
+                Err(e) => results[index] = Some(Err(e)),
+            }
+        }
+        pending = still_running;
+        if pending.is_empty() || start.elapsed() >= GRACE_PERIOD {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    // the programs that are still running are left running
+    results.into_iter().map(|result| result.unwrap_or(Ok(()))).collect()
+}
+
+/// The children that have not exited yet, or an error if one of them exited with an error
+fn still_running_children(children: Vec<Detached>) -> Result<Vec<Detached>> {
+    let mut running = Vec::new();
+    for mut detached in children {
+        match detached.child.try_wait()? {
+            Some(status) if !status.success() => {
+                debug!("Pid {} exited right away with {}", detached.child.id(), status);
+                bail!("{}", detached.failure);
+            }
+            Some(_) => (),
+            None => running.push(detached),
+        }
+    }
+    Ok(running)
+}
+
+/// Opens the log of detached commands for appending, creating it if needed
+fn open_log(path: &Path) -> Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .context(format!("Failed to create the directory {}", dir.display()))?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context(format!("Failed to open the log {}", path.display()))
+}
+
+/// Whether a command that needs a terminal can use the one opener is running in, which needs
+/// both stdin and stdout to be a terminal. When opener is run from a file manager or a launcher
+/// like rofi they are not.
+pub fn on_current_terminal(terminal: bool) -> bool {
+    terminal && io::stdin().is_terminal() && io::stdout().is_terminal()
+}
+
+/// Wraps a command so that it runs in a new terminal, unless opener is already running in one.
+/// The terminal is `terminal_command` from the config, or `$TERMINAL -e` or `xterm -e`.
+pub fn in_terminal(args: Vec<OsString>, options: &RunOptions) -> Vec<OsString> {
+    if on_current_terminal(true) {
+        return args;
+    }
+    match &options.terminal_command {
+        // the terminal command is a shell command that gets the command as its arguments
+        Some(terminal_command) => {
+            let script = format!("{} \"$@\"", terminal_command);
+            vec!["sh".into(), "-c".into(), script.into(), "sh".into()]
+                .into_iter()
+                .chain(args)
+                .collect()
+        }
+        None => {
+            let terminal = env::var("TERMINAL").unwrap_or_else(|_| DEFAULT_TERMINAL.to_string());
+            vec![terminal.into(), "-e".into()].into_iter().chain(args).collect()
+        }
+    }
+}
+
+/// The values a placeholder is replaced with, None if it is not a placeholder
+fn placeholder(c: char, paths: &[&Path], mime: &Mime) -> Result<Option<Vec<OsString>>> {
+    let first = paths
+        .first()
+        .ok_or_else(|| anyhow!("There must be at least one path to expand the command with"))?;
+
+    let values = match c {
+        'f' => vec![path_arg(first)],
+        'F' => path_args(paths),
+        'u' => vec![file_url(first)?.into()],
+        'd' => vec![first.parent().unwrap_or_else(|| Path::new("")).into()],
+        'n' => vec![first.file_name().unwrap_or_default().into()],
+        'e' => vec![first.extension().unwrap_or_default().into()],
+        'm' => vec![mime.essence_str().into()],
+        _ => return Ok(None),
+    };
+    Ok(Some(values))
+}
+
+/// Whether the words of a command take many files, which they do if they have `%F` or no
+/// placeholders for one file, because then all the paths are added to the end
+fn takes_many(words: &[&String]) -> bool {
+    let mut many = false;
+    let mut one = false;
+    for word in words {
+        let mut chars = word.chars();
+        while let Some(c) = chars.next() {
+            if c != PLACEHOLDER {
+                continue;
+            }
+            match chars.next() {
+                Some('F') => many = true,
+                Some('f') | Some('u') | Some('d') | Some('n') | Some('e') => one = true,
+                _ => (),
+            }
+        }
+    }
+    many || !one
+}
+
+/// Expands the placeholders in a command string using the paths that are being opened. The
+/// placeholders are:
+///
+/// - `%f` the path of the first file, with `./` in front if it starts with `-`
+/// - `%F` the paths of all the files, like `%f`
+/// - `%u` the `file://` url of the first file, or the URI if it is not a file
+/// - `%d` the directory containing the first file
+/// - `%n` the file name of the first file
+/// - `%e` the extension of the first file
+/// - `%m` the mime type that was detected for the first file
+/// - `%%` a literal `%`
+///
+/// Every substituted value is quoted for the shell. Unknown placeholders are left as they are. If
+/// the command does not have any placeholders, the quoted paths are appended to the end of it so
+/// that `sxiv` works the same as `sxiv %F`. Paths that are not valid UTF-8 are kept as they are.
+pub fn expand_command(cmd: &str, paths: &[&Path], mime: &Mime) -> Result<OsString> {
+    let mut expanded = OsString::with_capacity(cmd.len());
+    let mut substituted = false;
+    let mut chars = cmd.chars();
+
+    while let Some(c) = chars.next() {
+        if c != PLACEHOLDER {
+            push_char(&mut expanded, c);
+            continue;
+        }
+
+        let next = match chars.next() {
+            Some(next) => next,
+            None => {
+                push_char(&mut expanded, PLACEHOLDER);
+                break;
+            }
+        };
+        if next == PLACEHOLDER {
+            push_char(&mut expanded, PLACEHOLDER);
+            continue;
+        }
+        match placeholder(next, paths, mime)? {
+            Some(values) => {
+                expanded.push(quote_args(&values));
+                substituted = true;
+            }
+            // unknown placeholder, keep it as is
+            None => {
+                push_char(&mut expanded, PLACEHOLDER);
+                push_char(&mut expanded, next);
+            }
+        }
+    }
+
+    if !substituted {
+        expanded.push(" ");
+        expanded.push(quote_args(&path_args(paths)));
+    }
+
+    Ok(expanded)
+}
+
+/// Expands the placeholders in arguments that are not run with the shell, so nothing is quoted.
+/// An argument that is just `%F` becomes one argument for each path. If none of the arguments
+/// have placeholders, the paths are added after them.
+fn expand_args(args: &[String], paths: &[&Path], mime: &Mime) -> Result<Vec<OsString>> {
+    let mut expanded = Vec::new();
+    let mut substituted = false;
+    for arg in args {
+        if arg == "%F" {
+            expanded.extend(path_args(paths));
+            substituted = true;
+            continue;
+        }
+
+        let mut word = OsString::with_capacity(arg.len());
+        let mut chars = arg.chars();
+        while let Some(c) = chars.next() {
+            if c != PLACEHOLDER {
+                push_char(&mut word, c);
+                continue;
+            }
+            match chars.next() {
+                Some(PLACEHOLDER) => push_char(&mut word, PLACEHOLDER),
+                Some(next) => match placeholder(next, paths, mime)? {
+                    Some(values) => {
+                        word.push(join(&values, " "));
+                        substituted = true;
+                    }
+                    None => {
+                        push_char(&mut word, PLACEHOLDER);
+                        push_char(&mut word, next);
+                    }
+                },
+                None => push_char(&mut word, PLACEHOLDER),
+            }
+        }
+        expanded.push(word);
+    }
+
+    if !substituted {
+        expanded.extend(path_args(paths));
+    }
+    Ok(expanded)
+}
+
+/// A path as it is given to a command. A relative path that starts with `-` gets `./` in front of
+/// it so that the program does not take it for an option.
+fn path_arg(path: &Path) -> OsString {
+    if path.is_relative() && path.as_os_str().as_bytes().starts_with(b"-") {
+        Path::new(".").join(path).into_os_string()
+    } else {
+        path.into()
+    }
+}
+
+/// Each path as it is given to a command, see `path_arg`
+pub fn path_args(paths: &[&Path]) -> Vec<OsString> {
+    paths.iter().map(|path| path_arg(path)).collect()
+}
+
+/// Adds a character to the end of an OS string
+fn push_char(s: &mut OsString, c: char) {
+    s.push(c.encode_utf8(&mut [0; 4]));
+}
+
+/// Joins OS strings with a separator, like `join` for strings
+pub fn join<S: AsRef<OsStr>>(strings: &[S], separator: &str) -> OsString {
+    let mut joined = OsString::new();
+    for (i, s) in strings.iter().enumerate() {
+        if i > 0 {
+            joined.push(separator);
+        }
+        joined.push(s);
+    }
+    joined
+}
+
+/// Quotes an argument for the shell but leaves its placeholders outside of the quotes, so that
+/// they are expanded and quoted by `expand_command`
+fn quote_arg(arg: &str) -> String {
+    let mut quoted = String::new();
+    let mut literal = String::new();
+    let mut chars = arg.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == PLACEHOLDER && chars.peek().is_some_and(|next| *next != PLACEHOLDER) {
+            if !literal.is_empty() {
+                quoted.push_str(&quote(&literal));
+                literal.clear();
+            }
+            quoted.push(PLACEHOLDER);
+            quoted.extend(chars.next());
+        } else {
+            literal.push(c);
+            // a literal % stays escaped for expand_command
+            if c == PLACEHOLDER {
+                literal.extend(chars.next());
+            }
+        }
+    }
+    if !literal.is_empty() || quoted.is_empty() {
+        quoted.push_str(&quote(&literal));
+    }
+    quoted
+}
+
+/// Quotes each argument and joins them with spaces
+fn quote_args<S: AsRef<OsStr>>(args: &[S]) -> OsString {
+    let quoted: Vec<OsString> = args.iter().map(|arg| quote_os(arg.as_ref())).collect();
+    join(&quoted, " ")
+}
+
+/// Quotes a string for toml
+pub fn toml_string(s: &str) -> String {
+    toml::Value::String(s.to_string()).to_string()
+}
+
+/// Quotes a string so that the shell treats it as a single word.
+pub fn quote(s: &str) -> String {
+    String::from_utf8(quote_bytes(s.as_bytes())).expect("BUG: quoting keeps a string UTF-8")
+}
+
+/// Quotes an OS string like `quote`, the shell does not need paths to be valid UTF-8
+pub fn quote_os(s: &OsStr) -> OsString {
+    OsString::from_vec(quote_bytes(s.as_bytes()))
+}
+
+/// Quotes the bytes of a word for the shell. Only ASCII is special to the shell, so the other
+/// bytes are copied as they are.
+fn quote_bytes(s: &[u8]) -> Vec<u8> {
+    if !s.is_empty()
+        && s
+            .iter()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-_./,:=+@".contains(byte))
+    {
+        return s.to_vec();
+    }
+
+    let mut quoted = vec![b'\''];
+    for &byte in s {
+        if byte == b'\'' {
+            quoted.extend_from_slice(br#"'\''"#);
+        } else {
+            quoted.push(byte);
+        }
+    }
+    quoted.push(b'\'');
+    quoted
+}
+
+/// Whether the program is a path to a file or can be found on PATH
+pub fn is_executable(program: &str) -> bool {
+    if program.contains('/') {
+        return Path::new(program).is_file();
+    }
+
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+/// The paths separated by commas, for messages
+fn display_paths(paths: &[&Path]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Creates a `file://` url from a path, percent encoding everything that is not allowed in a url
+/// path. A URI that is being opened instead of a file is returned as it is.
+pub fn file_url(path: &Path) -> Result<String> {
+    if is_uri(path) {
+        return Ok(path.to_string_lossy().to_string());
+    }
+
+    let absolute: PathBuf = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir()?.join(path)
+    };
+
+    let mut url = String::from("file://");
+    for &byte in absolute.as_os_str().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            url.push(byte as char);
+        } else {
+            url.push_str(&format!("%{:02X}", byte));
+        }
+    }
+
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(cmd: &str, paths: &[&str]) -> OsString {
+        let paths: Vec<&Path> = paths.iter().map(Path::new).collect();
+        expand_command(cmd, &paths, &mime::TEXT_PLAIN).unwrap()
+    }
+
+    fn expand_words(args: &[&str], paths: &[&str]) -> Vec<OsString> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let paths: Vec<&Path> = paths.iter().map(Path::new).collect();
+        expand_args(&args, &paths, &mime::TEXT_PLAIN).unwrap()
+    }
+
+    #[test]
+    fn quote_leaves_plain_words_alone() {
+        assert_eq!(quote("dir/file-1_a.txt"), "dir/file-1_a.txt");
+    }
+
+    #[test]
+    fn quote_wraps_everything_else_in_single_quotes() {
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("a b"), "'a b'");
+        assert_eq!(quote("it's"), r#"'it'\''s'"#);
+        assert_eq!(quote("$(touch x)"), "'$(touch x)'");
+    }
+
+    #[test]
+    fn quoted_words_reach_the_program_unchanged() {
+        for word in [
+            "a b", "it's", "$HOME", "`id`", "a\nb", "*", "\\", "-n", "\"'\"",
+        ] {
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(format!("printf %s {}", quote(word)))
+                .output()
+                .unwrap();
+            assert_eq!(String::from_utf8_lossy(&output.stdout), word);
+        }
+    }
+
+    #[test]
+    fn quoted_bytes_reach_the_program_unchanged() {
+        let word = OsStr::from_bytes(b"it's \xff");
+        let mut script = OsString::from("printf %s ");
+        script.push(quote_os(word));
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .output()
+            .unwrap();
+        assert_eq!(output.stdout, word.as_bytes());
+    }
+
+    #[test]
+    fn paths_that_are_not_utf8_are_kept() {
+        let path = Path::new(OsStr::from_bytes(b"a\xffb"));
+        let cmd = expand_command("cat %f", &[path], &mime::TEXT_PLAIN).unwrap();
+        assert_eq!(cmd.as_bytes(), b"cat 'a\xffb'");
+        let cmd = expand_command("cat", &[path], &mime::TEXT_PLAIN).unwrap();
+        assert_eq!(cmd.as_bytes(), b"cat 'a\xffb'");
+
+        let args = ["--file=%f".to_string()];
+        let args = expand_args(&args, &[path], &mime::TEXT_PLAIN).unwrap();
+        assert_eq!(args[0].as_bytes(), b"--file=a\xffb");
+        let args = expand_args(&[], &[path], &mime::TEXT_PLAIN).unwrap();
+        assert_eq!(args[0].as_bytes(), b"a\xffb");
+
+        let url = file_url(Path::new(OsStr::from_bytes(b"/a\xff"))).unwrap();
+        assert_eq!(url, "file:///a%FF");
+    }
+
+    #[test]
+    fn paths_that_start_with_a_dash_are_not_options() {
+        assert_eq!(expand("rm %f", &["-rf.txt"]), "rm ./-rf.txt");
+        assert_eq!(expand("rm %F", &["a", "-b"]), "rm a ./-b");
+        assert_eq!(expand("rm", &["-rf.txt"]), "rm ./-rf.txt");
+        assert_eq!(expand("echo %n", &["dir/-a"]), "echo -a");
+        assert_eq!(expand("rm %f", &["/tmp/-a"]), "rm /tmp/-a");
+        assert_eq!(expand_words(&["%F"], &["-a"]), ["./-a"]);
+        assert_eq!(expand_words(&["--file=%f"], &["-a"]), ["--file=./-a"]);
+        assert_eq!(expand_words(&[], &["-a"]), ["./-a"]);
+    }
+
+    #[test]
+    fn expand_command_quotes_the_paths() {
+        assert_eq!(expand("sxiv %f", &["a b.png"]), "sxiv 'a b.png'");
+        assert_eq!(
+            expand("mpv %F", &["a.mp4", "b's.mp4"]),
+            r#"mpv a.mp4 'b'\''s.mp4'"#
+        );
+    }
+
+    #[test]
+    fn expand_command_fills_in_every_placeholder() {
+        assert_eq!(
+            expand("cd %d && edit %n %e %m", &["dir/x.tar.gz"]),
+            "cd dir && edit x.tar.gz gz text/plain"
+        );
+        assert_eq!(expand("open %u", &["/tmp/a b"]), "open 'file:///tmp/a%20b'");
+    }
+
+    #[test]
+    fn expand_command_appends_the_paths_without_placeholders() {
+        assert_eq!(
+            expand("sxiv", &["a b.png", "c.png"]),
+            "sxiv 'a b.png' c.png"
+        );
+        // %% and unknown placeholders are not placeholders for the paths
+        assert_eq!(expand("echo 100%%", &["a"]), "echo 100% a");
+        assert_eq!(expand("echo %x", &["a"]), "echo %x a");
+    }
+
+    #[test]
+    fn expand_command_keeps_a_trailing_percent() {
+        assert_eq!(expand("echo %", &["a"]), "echo % a");
+        assert_eq!(expand("echo %f %", &["a"]), "echo a %");
+    }
+
+    #[test]
+    fn expand_args_does_not_quote() {
+        assert_eq!(expand_words(&["--title=%n"], &["a b"]), ["--title=a b"]);
+        assert_eq!(
+            expand_words(&["--fs", "%F"], &["a b", "c"]),
+            ["--fs", "a b", "c"]
+        );
+    }
+
+    #[test]
+    fn expand_args_appends_the_paths_without_placeholders() {
+        assert_eq!(expand_words(&[], &["a b"]), ["a b"]);
+        assert_eq!(expand_words(&["100%%", "%"], &["a"]), ["100%", "%", "a"]);
+    }
+
+    #[test]
+    fn quote_arg_leaves_placeholders_outside_the_quotes() {
+        assert_eq!(quote_arg("%f"), "%f");
+        assert_eq!(quote_arg(""), "''");
+        assert_eq!(quote_arg("a b"), "'a b'");
+        assert_eq!(quote_arg("--title=%n x"), "--title=%n' x'");
+        assert_eq!(quote_arg("100%%"), "'100%%'");
+    }
+
+    #[test]
+    fn quoted_args_expand_to_the_same_words() {
+        let args = ["it's %n", "%%", "$HOME"];
+        let quoted: Vec<String> = args.iter().map(|arg| quote_arg(arg)).collect();
+        let cmd = format!("printf %%s'|' {}", quoted.join(" "));
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(expand(&cmd, &["a b"]))
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "it's a b|%|$HOME|");
+    }
+
+    #[test]
+    fn commands_are_strings_desktop_ids_or_tables() {
+        assert_eq!(
+            Command::parse("sxiv -a %f").unwrap(),
+            Command::Shell("sxiv -a %f".to_string())
+        );
+        assert_eq!(
+            Command::from_toml("\"sxiv\"").unwrap(),
+            Command::Shell("sxiv".to_string())
+        );
+        assert_eq!(
+            Command::parse("{ desktop = \"org.pwmt.zathura.desktop\" }").unwrap(),
+            Command::Desktop {
+                desktop: "org.pwmt.zathura.desktop".to_string()
+            }
+        );
+        assert_eq!(
+            Command::parse("{ cmd = \"mpv\" }").unwrap(),
+            Command::Structured(StructuredCommand {
+                cmd: "mpv".to_string(),
+                args: Vec::new(),
+                terminal: false,
+                detach: None,
+                env: BTreeMap::new(),
+                cwd: None,
+                shell: true,
+            })
+        );
+    }
+
+    #[test]
+    fn tables_with_unknown_or_missing_keys_are_not_commands() {
+        for invalid in &[
+            "{ cmd = \"mpv\", detached = true }",
+            "{ args = [\"--fs\"] }",
+            "{ cmd = \"mpv\", args = \"--fs\" }",
+            "3",
+        ] {
+            let e = Command::from_toml(invalid).unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                format!("The command {} is not valid", invalid)
+            );
+            assert!(format!("{:?}", e).contains("a command should be a string or a table"));
+        }
+    }
+
+    #[test]
+    fn commands_are_shown_as_they_are_written_in_the_config() {
+        let commands = [
+            "\"sxiv -a \\\"%f\\\"\"",
+            "{ desktop = \"org.pwmt.zathura.desktop\" }",
+            "{ cmd = \"mpv\" }",
+            "{ cmd = \"mpv\", args = [\"--fs\", \"%F\"], terminal = true, detach = false, \
+             env = { \"A\" = \"1\", \"B C\" = \"2\" }, cwd = \"file_dir\", shell = false }",
+        ];
+        for written in &commands {
+            let command = Command::from_toml(written).unwrap();
+            assert_eq!(command.to_toml(), *written);
+            assert_eq!(Command::from_toml(&command.to_toml()).unwrap(), command);
+        }
+    }
+
+    #[test]
+    fn tables_are_shown_without_the_default_options() {
+        let command = Command::parse("{ shell = true, args = [], cmd = 'mpv', env = {} }").unwrap();
+        assert_eq!(command.to_string(), "{ cmd = \"mpv\" }");
+    }
+
+    fn detach(script: &str) -> Detached {
+        let args: Vec<OsString> = vec!["sh".into(), "-c".into(), script.into()];
+        let launch = execute(&args, &BTreeMap::new(), None, true, &RunOptions::default()).unwrap();
+        launch.check(format!("{} failed", script)).unwrap().unwrap()
+    }
+
+    #[test]
+    fn detached_runs_share_one_grace_period() {
+        let start = Instant::now();
+        let runs = vec![
+            vec![detach("sleep 1")],
+            vec![detach("exit 1"), detach("sleep 1")],
+            vec![detach("exit 0")],
+            vec![detach("sleep 1")],
+        ];
+        let results = check_detached(runs);
+        assert!(start.elapsed() < GRACE_PERIOD * 2);
+        let failed: Vec<bool> = results.iter().map(Result::is_err).collect();
+        assert_eq!(failed, [false, true, false, false]);
+        assert_eq!(
+            results[1].as_ref().unwrap_err().to_string(),
+            "exit 1 failed"
+        );
+    }
+}
@@ -1,9 +1,16 @@
+mod check;
 mod edit_config;
+mod include;
 mod open_config;
 mod utils;
 
-use utils::{load_to_string, store_string};
+use utils::{find_local_config, layer_paths, load_to_edit, load_to_string, store_string};
+
+pub use utils::{default_config, store_default, ConfigPath};
+pub use check::{check, Severity};
 
 pub use open_config::OpenConfig;
 pub use open_config::PossibleMimes;
-pub use edit_config::EditConfig;
+pub use open_config::RuleEntry;
+pub use open_config::Narrowable;
+pub use edit_config::{mime_key, EditConfig};
@@ -0,0 +1,619 @@
+use std::collections::HashMap;
+
+use mime::Mime;
+use regex::Regex;
+use toml::Value;
+
+use crate::chooser::PROMPT;
+use crate::command::is_executable;
+use crate::desktop;
+
+/// The keys that are allowed at the top of the config
+const KNOWN_KEYS: &[&str] = &[
+    "open",
+    "open_regex",
+    "preview",
+    "preview_regex",
+    "chooser",
+    "detach",
+    "detach_log",
+    "terminal_command",
+    "url_probe",
+    "url_probe_cache",
+    "url_probe_ttl",
+    "detection",
+    "warn_mismatch",
+    "include",
+    "trusted_dirs",
+];
+
+/// The ways to find mimes that `detection` can list
+const DETECTORS: &[&str] = &["extension", "magic", "shebang", "xattr"];
+
+/// The keys that are allowed in a command that is a table
+const COMMAND_KEYS: &[&str] = &[
+    "desktop", "cmd", "args", "terminal", "detach", "env", "cwd", "shell",
+];
+
+/// The sections whose keys are mimes
+const MIME_SECTIONS: &[&str] = &["open", "preview"];
+
+/// The sections whose keys are regexes
+const REGEX_SECTIONS: &[&str] = &["open_regex", "preview_regex"];
+
+/// Shell builtins that are fine to use as the program of a command
+const BUILTINS: &[&str] = &[
+    ":", ".", "[", "cd", "echo", "eval", "false", "printf", "source", "test", "true",
+];
+
+/// Shell builtins that run the program after them
+const PREFIXES: &[&str] = &["command", "exec"];
+
+/// How bad a problem is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in the config and where it is
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The line and column, starting at 1. None if the location is not known.
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+/// Checks the contents of a config file and returns every problem found in it
+pub fn check(source: &str) -> Vec<Diagnostic> {
+    let root = match source.parse::<Value>() {
+        Ok(Value::Table(root)) => root,
+        Ok(_) => unreachable!("BUG: a toml document is always a table"),
+        Err(e) => {
+            return vec![Diagnostic {
+                severity: Severity::Error,
+                position: e.line_col().map(|(line, column)| (line + 1, column + 1)),
+                message: format!("invalid toml: {}", e),
+            }]
+        }
+    };
+
+    let locations = Locations::new(source);
+    let mut diagnostics = Vec::new();
+    let mut report = |severity, location: Option<&Location>, message: String| {
+        diagnostics.push(Diagnostic {
+            severity,
+            position: location.map(|l| (l.line, l.column)),
+            message,
+        })
+    };
+
+    for (key, value) in &root {
+        if !KNOWN_KEYS.contains(&key.as_str()) {
+            report(
+                Severity::Error,
+                locations.find(None, 0, key),
+                format!("unknown key {}, expected one of {}", key, KNOWN_KEYS.join(", ")),
+            );
+            continue;
+        }
+
+        if key == "chooser" || key == "terminal_command" || key == "url_probe" {
+            match value.as_str() {
+                // the built in prompt is not a program
+                Some(PROMPT) if key == "chooser" => (),
+                Some(command) => {
+                    if let Some(message) = missing_program(command) {
+                        report(Severity::Warning, locations.find(None, 0, key), message)
+                    }
+                }
+                None => report(
+                    Severity::Error,
+                    locations.find(None, 0, key),
+                    format!("{} should be a string", key),
+                ),
+            }
+            continue;
+        }
+
+        if [
+            "detach",
+            "detach_log",
+            "warn_mismatch",
+            "url_probe_cache",
+            "url_probe_ttl",
+        ]
+        .contains(&key.as_str())
+        {
+            let expected = match key.as_str() {
+                "detach" | "warn_mismatch" if !value.is_bool() => Some("true or false"),
+                "detach_log" | "url_probe_cache" if !value.is_str() => Some("a path"),
+                "url_probe_ttl" if value.as_integer().is_none_or(|ttl| ttl < 0) => {
+                    Some("a number of seconds")
+                }
+                _ => None,
+            };
+            if let Some(expected) = expected {
+                report(
+                    Severity::Error,
+                    locations.find(None, 0, key),
+                    format!("{} should be {}", key, expected),
+                );
+            }
+            continue;
+        }
+
+        if key == "detection" {
+            let message = match value.as_array() {
+                Some(detectors) if detectors.is_empty() => {
+                    Some("detection needs at least one way to find mimes".to_string())
+                }
+                Some(detectors) => detectors
+                    .iter()
+                    .find(|detector| !detector.as_str().is_some_and(|d| DETECTORS.contains(&d)))
+                    .map(|detector| {
+                        format!(
+                            "unknown detection {}, expected one of {}",
+                            detector,
+                            DETECTORS.join(", ")
+                        )
+                    }),
+                None => Some(format!("detection should be a list of {}", DETECTORS.join(", "))),
+            };
+            if let Some(message) = message {
+                report(Severity::Error, locations.find(None, 0, key), message);
+            }
+            continue;
+        }
+
+        if key == "include" || key == "trusted_dirs" {
+            let is_strings = value
+                .as_array()
+                .is_some_and(|paths| paths.iter().all(Value::is_str));
+            if !is_strings {
+                report(
+                    Severity::Error,
+                    locations.find(None, 0, key),
+                    format!("{} should be an array of paths", key),
+                )
+            }
+            continue;
+        }
+
+        let groups = match value.as_array() {
+            Some(groups) if groups.iter().all(Value::is_table) => groups,
+            _ => {
+                report(
+                    Severity::Error,
+                    locations.find(None, 0, key),
+                    format!("{} should be an array of tables like [[{}]]", key, key),
+                );
+                continue;
+            }
+        };
+
+        // the first group each pattern and command is in
+        let mut first_groups: HashMap<(&str, String), usize> = HashMap::new();
+        for (group, table) in groups.iter().enumerate() {
+            let table = table.as_table().expect("BUG: checked that it is a table");
+            let mut patterns: Vec<&String> = table.keys().collect();
+            patterns.sort();
+            for pattern in patterns {
+                let location = locations.find(Some(key), group, pattern);
+                let at = format!("{} in [[{}]] group {}", pattern, key, group);
+
+                if MIME_SECTIONS.contains(&key.as_str()) {
+                    if let Err(e) = pattern.parse::<Mime>() {
+                        report(Severity::Error, location, format!("invalid mime {}: {}", at, e));
+                    }
+                } else if REGEX_SECTIONS.contains(&key.as_str()) {
+                    if let Err(e) = Regex::new(pattern) {
+                        let e = e.to_string();
+                        let e = e.lines().last().unwrap_or_default().trim_start_matches("error: ");
+                        report(Severity::Error, location, format!("invalid regex {}: {}", at, e));
+                    }
+                }
+
+                // a later group with a different command is a fall back, but the same command
+                // again is never useful
+                let rule = (pattern.as_str(), table[pattern].to_string());
+                match first_groups.get(&rule) {
+                    Some(first) => report(
+                        Severity::Warning,
+                        location,
+                        format!(
+                            "duplicate {}, group {} already has the same command",
+                            at, first
+                        ),
+                    ),
+                    None => {
+                        first_groups.insert(rule, group);
+                    }
+                }
+
+                match &table[pattern] {
+                    Value::String(command) => {
+                        if let Some(message) = missing_program(command) {
+                            report(Severity::Warning, location, format!("{} for {}", message, at))
+                        }
+                    }
+                    Value::Table(command) => {
+                        for (severity, message) in command_table_problems(command) {
+                            report(severity, location, format!("{} for {}", message, at))
+                        }
+                    }
+                    _ => report(
+                        Severity::Error,
+                        location,
+                        format!(
+                            "the command for {} should be a string or a table like {{ cmd = \"...\" }}",
+                            at
+                        ),
+                    ),
+                }
+            }
+        }
+    }
+
+    // show the problems in the order they are in the file
+    diagnostics.sort_by_key(|d| d.position.unwrap_or((usize::MAX, 0)));
+    diagnostics
+}
+
+/// Finds the problems in a command that is a table, like `{ cmd = "..." }` or
+/// `{ desktop = "..." }`
+fn command_table_problems(command: &toml::value::Table) -> Vec<(Severity, String)> {
+    let mut problems = Vec::new();
+    for key in command.keys() {
+        if !COMMAND_KEYS.contains(&key.as_str()) {
+            problems.push((
+                Severity::Error,
+                format!(
+                    "unknown key {} in the command, expected one of {}",
+                    key,
+                    COMMAND_KEYS.join(", ")
+                ),
+            ));
+        }
+    }
+
+    let error = |message: &str| (Severity::Error, message.to_string());
+    match (command.get("desktop"), command.get("cmd")) {
+        (Some(_), Some(_)) => {
+            problems.push(error("the command table can not have both desktop and cmd"))
+        }
+        (None, None) => problems.push(error("the command table needs a cmd or a desktop key")),
+        (Some(Value::String(id)), None) => {
+            if desktop::find(id).is_none() {
+                problems.push((
+                    Severity::Warning,
+                    format!("the desktop file {} was not found", id),
+                ));
+            }
+            // the options of the application come from its desktop file
+            for key in command.keys().filter(|key| *key != "desktop") {
+                if COMMAND_KEYS.contains(&key.as_str()) {
+                    problems.push((
+                        Severity::Error,
+                        format!("{} can not be used with desktop", key),
+                    ));
+                }
+            }
+        }
+        (Some(_), None) => problems.push(error("desktop should be a string")),
+        (None, Some(Value::String(cmd))) => {
+            let shell = command.get("shell").and_then(Value::as_bool).unwrap_or(true);
+            let missing = if shell {
+                missing_program(cmd)
+            } else if is_executable(cmd) {
+                None
+            } else {
+                Some(format!("the program {} was not found on PATH", cmd))
+            };
+            problems.extend(missing.map(|message| (Severity::Warning, message)));
+        }
+        (None, Some(_)) => problems.push(error("cmd should be a string")),
+    }
+
+    let is_strings = |value: &Value| match value {
+        Value::Array(values) => values.iter().all(Value::is_str),
+        Value::Table(values) => values.values().all(Value::is_str),
+        _ => false,
+    };
+    for (key, value) in command {
+        let expected = match key.as_str() {
+            "args" if !(value.is_array() && is_strings(value)) => "an array of strings",
+            "env" if !(value.is_table() && is_strings(value)) => "a table of strings",
+            "terminal" | "detach" | "shell" if !value.is_bool() => "true or false",
+            "cwd" if !value.is_str() => "a string",
+            _ => continue,
+        };
+        problems.push((Severity::Error, format!("{} should be {}", key, expected)));
+    }
+    problems
+}
+
+/// Returns a message if the program a command runs cannot be found
+fn missing_program(command: &str) -> Option<String> {
+    let program = program(command)?;
+    if BUILTINS.contains(&program.as_str()) || is_executable(&program) {
+        None
+    } else {
+        Some(format!("the program {} was not found on PATH", program))
+    }
+}
+
+/// Finds the program that a shell command runs, skipping variable assignments and builtins like
+/// exec. Returns None if there is no program or it comes from a variable like $EDITOR.
+fn program(command: &str) -> Option<String> {
+    command
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c| c == '\'' || c == '"'))
+        .find(|word| !word.contains('=') && !PREFIXES.contains(word))
+        .filter(|word| !word.starts_with('$'))
+        .map(str::to_string)
+}
+
+/// Where a key is in the source of the config
+#[derive(Debug)]
+struct Location {
+    /// The array of tables the key is in, None if it is at the top of the file
+    section: Option<String>,
+    /// The index of the table in the array
+    group: usize,
+    key: String,
+    line: usize,
+    column: usize,
+}
+
+/// The locations of every key and table header in the config. The toml parser does not keep
+/// track of where keys are, so this scans the lines of the file itself.
+struct Locations(Vec<Location>);
+
+impl Locations {
+    fn new(source: &str) -> Locations {
+        let mut locations = Vec::new();
+        let mut section: Option<String> = None;
+        let mut group = 0;
+        // how many times each array of tables has been seen
+        let mut counts: HashMap<String, usize> = HashMap::new();
+
+        for (line_idx, line) in source.lines().enumerate() {
+            let trimmed = line.trim_start();
+            let column = line.len() - trimmed.len() + 1;
+            let header = if let Some(rest) = trimmed.strip_prefix("[[") {
+                rest.split("]]").next()
+            } else if let Some(rest) = trimmed.strip_prefix('[') {
+                rest.split(']').next()
+            } else {
+                None
+            };
+
+            if let Some(name) = header {
+                let name = name.trim().trim_matches(|c| c == '"' || c == '\'').to_string();
+                // the header is where the key of the table is at the top of the file
+                locations.push(Location {
+                    section: None,
+                    group: 0,
+                    key: name.clone(),
+                    line: line_idx + 1,
+                    column,
+                });
+                let count = counts.entry(name.clone()).or_insert(0);
+                group = *count;
+                *count += 1;
+                section = Some(name);
+            } else if let Some(key) = parse_key(trimmed) {
+                locations.push(Location {
+                    section: section.clone(),
+                    group,
+                    key,
+                    line: line_idx + 1,
+                    column,
+                });
+            }
+        }
+
+        Locations(locations)
+    }
+
+    /// Finds the first location of a key
+    fn find(&self, section: Option<&str>, group: usize, key: &str) -> Option<&Location> {
+        self.0.iter().find(|location| {
+            location.section.as_deref() == section && location.group == group && location.key == key
+        })
+    }
+}
+
+/// Parses the key at the start of a `key = value` line
+fn parse_key(line: &str) -> Option<String> {
+    let mut chars = line.char_indices();
+    let (_, first) = chars.next()?;
+    let mut key = String::new();
+    // the byte index right after the key
+    let mut end = line.len();
+
+    match first {
+        '"' => {
+            let mut escaped = false;
+            for (i, c) in chars {
+                match c {
+                    _ if escaped => {
+                        key.push(c);
+                        escaped = false;
+                    }
+                    '\\' => escaped = true,
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    c => key.push(c),
+                }
+            }
+        }
+        '\'' => {
+            for (i, c) in chars {
+                if c == '\'' {
+                    end = i + 1;
+                    break;
+                }
+                key.push(c);
+            }
+        }
+        c if c.is_ascii_alphanumeric() || c == '-' || c == '_' => {
+            key.push(c);
+            for (i, c) in chars {
+                if !(c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                    end = i;
+                    break;
+                }
+                key.push(c);
+            }
+        }
+        _ => return None,
+    }
+
+    // only keys that are followed by = count, anything else is part of a value
+    if line[end..].trim_start().starts_with('=') {
+        Some(key)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The diagnostics as `line:column: message`, with `warning: ` before the message of warnings
+    fn diagnostics(source: &str) -> Vec<String> {
+        check(source)
+            .into_iter()
+            .map(|d| {
+                let (line, column) = d.position.unwrap_or_default();
+                let severity = match d.severity {
+                    Severity::Error => "",
+                    Severity::Warning => "warning: ",
+                };
+                format!("{}:{}: {}{}", line, column, severity, d.message)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn valid_config_has_no_diagnostics() {
+        let source = r#"
+chooser = "prompt"
+detach = true
+detection = ["xattr", "extension"]
+
+[[open]]
+"image/*" = "sh -c 'exec true'"
+"text/plain" = { cmd = "true", args = ["--fs"], terminal = true }
+
+[[open_regex]]
+'\.log$' = "echo"
+"#;
+        assert_eq!(diagnostics(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn unknown_keys_are_errors() {
+        let source = "detach = true\n  colour = 'red'\n";
+        assert_eq!(
+            diagnostics(source),
+            [format!(
+                "2:3: unknown key colour, expected one of {}",
+                KNOWN_KEYS.join(", ")
+            )]
+        );
+
+        let source = "[[open]]\n'text/plain' = { cmd = 'true', wait = true }\n";
+        assert_eq!(
+            diagnostics(source),
+            [format!(
+                "2:1: unknown key wait in the command, expected one of {} for text/plain in [[open]] group 0",
+                COMMAND_KEYS.join(", ")
+            )]
+        );
+    }
+
+    #[test]
+    fn invalid_mimes_and_regexes_are_errors() {
+        let source = "[[open]]\n'text' = 'true'\n\n[[open_regex]]\n    '(a' = 'true'\n";
+        let diagnostics = diagnostics(source);
+        assert_eq!(diagnostics.len(), 2, "{:?}", diagnostics);
+        assert!(diagnostics[0].starts_with("2:1: invalid mime text in [[open]] group 0: "));
+        assert!(diagnostics[1].starts_with("5:5: invalid regex (a in [[open_regex]] group 0: "));
+    }
+
+    #[test]
+    fn the_same_rule_in_a_later_group_is_a_warning() {
+        let source = "[[open]]\n'text/plain' = 'true'\n\n[[open]]\n\
+                      'text/plain' = 'true'\n'text/html' = 'true'\n\n[[open]]\n'text/plain' = 'echo'\n";
+        assert_eq!(
+            diagnostics(source),
+            ["5:1: warning: duplicate text/plain in [[open]] group 1, group 0 already has the same command"]
+        );
+    }
+
+    #[test]
+    fn missing_programs_are_warnings() {
+        let source = "url_probe = 'no-such-program-for-opener -sI'\n\
+                      [[open]]\n'text/plain' = 'A=1 exec no-such-program-for-opener %f'\n\
+                      'text/html' = '$BROWSER'\n";
+        assert_eq!(
+            diagnostics(source),
+            [
+                "1:1: warning: the program no-such-program-for-opener was not found on PATH",
+                "3:1: warning: the program no-such-program-for-opener was not found on PATH for \
+                 text/plain in [[open]] group 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn values_of_the_wrong_type_are_errors() {
+        let source = "detach = 'yes'\nopen = 'sxiv'\ninclude = '~/a.toml'\ndetection = []\n";
+        assert_eq!(
+            diagnostics(source),
+            [
+                "1:1: detach should be true or false",
+                "2:1: open should be an array of tables like [[open]]",
+                "3:1: include should be an array of paths",
+                "4:1: detection needs at least one way to find mimes",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_toml_is_reported_where_it_is() {
+        let diagnostics = diagnostics("detach = true\n[[open]\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].starts_with("2:"), "{}", diagnostics[0]);
+        assert!(
+            diagnostics[0].contains("invalid toml"),
+            "{}",
+            diagnostics[0]
+        );
+    }
+
+    #[test]
+    fn locations_count_the_groups_of_each_section() {
+        let locations = Locations::new(
+            "[[open]]\na = 1\n[[preview]]\na = 1\n[[open]]\n  \"b c\" = 1\n  'a' = 1\n",
+        );
+        let position = |section, group, key| {
+            locations
+                .find(section, group, key)
+                .map(|l| (l.line, l.column))
+        };
+        assert_eq!(position(None, 0, "open"), Some((1, 1)));
+        assert_eq!(position(Some("open"), 0, "a"), Some((2, 1)));
+        assert_eq!(position(Some("preview"), 0, "a"), Some((4, 1)));
+        assert_eq!(position(Some("open"), 1, "b c"), Some((6, 3)));
+        assert_eq!(position(Some("open"), 1, "a"), Some((7, 3)));
+        assert_eq!(position(Some("open"), 2, "a"), None);
+    }
+}
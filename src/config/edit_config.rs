@@ -1,18 +1,38 @@
-use super::{store_string, load_to_string};
-use anyhow::Result;
-use toml_edit::{ArrayOfTables, Document, Item, Table};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::{load_to_edit, store_string, ConfigPath};
+use crate::command::Command;
+use crate::mime_helpers::same_mime;
+use anyhow::{anyhow, Context, Result};
+use log::*;
+use mime::Mime;
+use toml_edit::{ArrayOfTables, Document, Item, Table, Value};
 
 /// The config that will be parsed into if editing the toml file is needed.
 #[derive(Debug)]
 pub struct EditConfig {
     doc: Document,
+    /// Where the config was loaded from and will be stored to
+    path: PathBuf,
 }
 
 impl EditConfig {
-    pub fn load() -> Result<Self> {
-        let toml_string = load_to_string()?;
-        let doc = toml_string.parse::<Document>().expect("invalid doc");
-        Ok(EditConfig { doc })
+    /// Loads the config to change it. A config that does not exist yet starts empty unless it is
+    /// the only layer, then it starts from the default config.
+    pub fn load(config_path: &ConfigPath) -> Result<Self> {
+        EditConfig::parse(config_path, &load_to_edit(config_path)?)
+    }
+
+    /// Parses the contents of the config that will be stored to the path
+    pub fn parse(path: &Path, toml_string: &str) -> Result<Self> {
+        let doc = toml_string
+            .parse::<Document>()
+            .context("The config file is not valid toml, run opener check to see why")?;
+        Ok(EditConfig {
+            doc,
+            path: path.to_path_buf(),
+        })
     }
 
     pub fn root(&mut self) -> &mut Item {
@@ -26,28 +46,128 @@ impl EditConfig {
     }
 
     pub fn get_open(&mut self) -> Result<&mut ArrayOfTables> {
-        let array = self
-            .root_table()
-            .entry("open")
-            .as_array_of_tables_mut()
-            .unwrap();
-        Ok(array)
+        self.array_of_tables("open")
     }
 
     pub fn get_preview(&mut self) -> Result<&mut ArrayOfTables> {
-        let array = self
-            .root_table()
-            .entry("preview")
-            .as_array_of_tables_mut()
-            .unwrap();
-        Ok(array)
+        self.array_of_tables("preview")
+    }
+
+    /// The groups of a section like `[[open]]`. A config without the section, like one that only
+    /// has includes or whose last group was unset, gets an empty one.
+    fn array_of_tables(&mut self, key: &str) -> Result<&mut ArrayOfTables> {
+        let item = self.root_table().entry(key);
+        if item.is_none() {
+            *item = Item::ArrayOfTables(ArrayOfTables::new());
+        }
+        item.as_array_of_tables_mut().ok_or_else(|| {
+            anyhow!("{} in the config should be an array of tables like [[{}]]", key, key)
+        })
+    }
+
+    /// Sets the command for a mime in the first group of open or preview that does not have the
+    /// mime yet, creating a new group if they all have it. Returns false if a group already has
+    /// the same command for the mime, because then there is nothing to do. The config is not
+    /// stored.
+    pub fn set(&mut self, preview: bool, mime_str: &str, command: &Command) -> Result<bool> {
+        let new_value = command
+            .to_toml()
+            .parse::<Value>()
+            .map_err(|e| anyhow!("Failed to convert the command {} to toml: {}", command, e))?;
+        let array = if preview {
+            self.get_preview()?
+        } else {
+            self.get_open()?
+        };
+
+        let mut idx = 0;
+        // for each table in open
+        while let Some(table) = array.get_mut(idx) {
+            debug!("Table: {:?}", table);
+
+            // if there is already a key for the mime in the table, however its parameters are
+            // written
+            let existing = mime_key(table, mime_str);
+            if let Some(value) = existing.and_then(|key| table.get(&key)?.as_value()) {
+                // check if the value is equal to the command added, tables are compared by what
+                // they contain rather than how they are written
+                if Command::from_toml(&value.to_string()).ok().as_ref() == Some(command) {
+                    // if the value is equal, the command for the associated mime type is already
+                    // there, do nothing
+                    info!("The mime {} already has a command", mime_str);
+                    return Ok(false);
+                } else {
+                    // if there is already a key but the value is not the same, skip this table and go
+                    // to the next one
+                    idx += 1;
+                    continue;
+                }
+            } else {
+                // if there is not already the specified mime_str in the table, check just to make sure
+                if let Some(value) = table.get(mime_str) {
+                    debug!("Option<Item>: {:?}", value);
+                    if value.as_str().is_some() {
+                        panic!("Hash to be Item: None")
+                    }
+                }
+                // insert pair
+                info!("inserting pair into table");
+                table[mime_str] = toml_edit::value(new_value);
+                return Ok(true);
+            }
+        }
+
+        // if there are no more tables
+        // there must have been no tables to insert in so create a new one
+        info!("Appending new table");
+        let mut table = Table::new();
+        table[mime_str] = toml_edit::value(new_value);
+        array.append(table);
+
+        Ok(true)
     }
 
     pub fn store(&self) -> Result<()> {
-        store_string(&self.doc.to_string())
+        store_string(&self.path, &self.doc.to_string())
     }
+}
+
+/// The key of the table that is the same mime as `mime_str`, including the parameters in any
+/// order. Keys that are not valid mimes only match if they are written the same.
+pub fn mime_key(table: &Table, mime_str: &str) -> Option<String> {
+    let mime = mime_str.parse::<Mime>().ok();
+    table
+        .iter()
+        .map(|(key, _)| key)
+        .find(|key| match (&mime, key.parse::<Mime>()) {
+            (Some(mime), Ok(rule)) => same_mime(mime, &rule),
+            _ => *key == mime_str,
+        })
+        .map(str::to_string)
+}
+
+impl fmt::Display for EditConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.doc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_finds_the_mime_with_parameters_however_it_is_written() {
+        let source = "[[open]]\n\"text/plain;charset=utf-8\" = \"less\"\n";
+        let mut cfg = EditConfig::parse(Path::new("config.toml"), source).unwrap();
+        let less = Command::Shell("less".to_string());
+        assert!(!cfg.set(false, "text/plain; charset=UTF-8", &less).unwrap());
 
-    pub fn to_string(&self) -> String {
-        self.doc.to_string()
+        // without the parameters it is another mime, so it goes in the same group
+        assert!(cfg.set(false, "text/plain", &less).unwrap());
+        assert_eq!(
+            cfg.to_string(),
+            "[[open]]\n\"text/plain;charset=utf-8\" = \"less\"\n\"text/plain\" = \"less\"\n"
+        );
     }
 }
@@ -0,0 +1,191 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use directories::BaseDirs;
+
+/// Resolves an include pattern to the files it refers to. A leading `~` is the home directory and
+/// relative patterns are relative to `dir`, the directory of the config that includes them. The
+/// file names can use `*`, `?` and `[...]` like a shell glob. A pattern without any of those is
+/// returned as is even if the file does not exist, but a glob that matches nothing returns
+/// nothing. The matches of a glob are sorted.
+pub fn resolve(pattern: &str, dir: &Path) -> Result<Vec<PathBuf>> {
+    let path = expand_tilde(pattern)?;
+    let path = dir.join(path);
+
+    if !is_glob(&path.to_string_lossy()) {
+        return Ok(vec![path]);
+    }
+
+    let mut matches = vec![PathBuf::new()];
+    for component in path.components() {
+        let name = component.as_os_str().to_string_lossy();
+        if !matches!(component, Component::Normal(_)) || !is_glob(&name) {
+            for path in &mut matches {
+                path.push(component);
+            }
+            continue;
+        }
+
+        let pattern: Vec<char> = name.chars().collect();
+        let mut next = Vec::new();
+        for dir in &matches {
+            // directories that cannot be read just do not match anything
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let file_name = entry.file_name();
+                let file_name = file_name.to_string_lossy();
+                // like the shell, hidden files are only matched by patterns starting with a dot
+                if file_name.starts_with('.') && pattern.first() != Some(&'.') {
+                    continue;
+                }
+                let chars: Vec<char> = file_name.chars().collect();
+                if glob_match(&pattern, &chars) {
+                    next.push(dir.join(&*file_name));
+                }
+            }
+        }
+        matches = next;
+    }
+
+    matches.retain(|path| path.is_file());
+    matches.sort();
+    Ok(matches)
+}
+
+/// Replaces a leading `~` with the home directory
+pub fn expand_tilde(pattern: &str) -> Result<PathBuf> {
+    let rest = match pattern.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest.trim_start_matches('/'),
+        _ => return Ok(PathBuf::from(pattern)),
+    };
+    let base = BaseDirs::new().ok_or_else(|| anyhow!("Failed to find the home directory"))?;
+    Ok(base.home_dir().join(rest))
+}
+
+/// Whether a string has any glob characters in it
+fn is_glob(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// Matches a file name against a glob pattern
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        // a star matches any number of characters, so try every split of the name
+        Some('*') => (0..=name.len()).any(|i| glob_match(&pattern[1..], &name[i..])),
+        Some('?') => !name.is_empty() && glob_match(&pattern[1..], &name[1..]),
+        Some('[') => match class_match(&pattern[1..], name.first()) {
+            Some((matched, rest)) => matched && !name.is_empty() && glob_match(rest, &name[1..]),
+            // a bracket without a closing bracket is a literal bracket
+            None => name.first() == Some(&'[') && glob_match(&pattern[1..], &name[1..]),
+        },
+        Some(c) => name.first() == Some(c) && glob_match(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Matches a character against a class like `[a-z]` or `[!0-9]`, where `pattern` is everything
+/// after the opening bracket. Returns whether it matched and the pattern after the closing
+/// bracket, or None if the class is never closed.
+fn class_match<'a>(pattern: &'a [char], c: Option<&char>) -> Option<(bool, &'a [char])> {
+    let (negated, pattern) = match pattern.first() {
+        Some('!') | Some('^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+
+    let mut matched = false;
+    let mut i = 0;
+    // a closing bracket right at the start is part of the class
+    while i < pattern.len() && (i == 0 || pattern[i] != ']') {
+        let start = pattern[i];
+        let (end, next) = match (pattern.get(i + 1), pattern.get(i + 2)) {
+            (Some('-'), Some(&end)) if end != ']' => (end, i + 3),
+            _ => (start, i + 1),
+        };
+        if c.is_some_and(|&c| start <= c && c <= end) {
+            matched = true;
+        }
+        i = next;
+    }
+
+    if i >= pattern.len() {
+        return None;
+    }
+    Some((matched != negated, &pattern[i + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        glob_match(&pattern, &name)
+    }
+
+    fn class(pattern: &str, c: char) -> Option<(bool, String)> {
+        let pattern: Vec<char> = pattern.chars().collect();
+        class_match(&pattern, Some(&c)).map(|(matched, rest)| (matched, rest.iter().collect()))
+    }
+
+    #[test]
+    fn star_matches_any_number_of_characters() {
+        assert!(matches("*.toml", "a.toml"));
+        assert!(matches("*.toml", ".toml"));
+        assert!(matches("a*b*c", "aXbYYc"));
+        assert!(!matches("*.toml", "a.toml.bak"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(matches("?.toml", "a.toml"));
+        assert!(!matches("?.toml", "ab.toml"));
+        assert!(!matches("?", ""));
+    }
+
+    #[test]
+    fn literal_characters_match_exactly() {
+        assert!(matches("team.toml", "team.toml"));
+        assert!(!matches("team.toml", "team.tom"));
+        assert!(matches("", ""));
+    }
+
+    #[test]
+    fn brackets_match_a_class() {
+        assert!(matches("[0-9]-*.toml", "5-video.toml"));
+        assert!(!matches("[0-9]-*.toml", "a-video.toml"));
+        assert!(matches("[!a]*", "b"));
+        assert!(!matches("[!a]*", "a"));
+        assert!(!matches("[ab]", ""));
+    }
+
+    #[test]
+    fn unclosed_bracket_is_literal() {
+        assert!(matches("[ab", "[ab"));
+        assert!(!matches("[ab", "a"));
+    }
+
+    #[test]
+    fn class_match_returns_the_rest_of_the_pattern() {
+        assert_eq!(class("a-c]x", 'b'), Some((true, "x".to_string())));
+        assert_eq!(class("^a-c]x", 'b'), Some((false, "x".to_string())));
+        assert_eq!(class("xyz]", 'b'), Some((false, String::new())));
+        assert_eq!(class("abc", 'b'), None);
+    }
+
+    #[test]
+    fn closing_bracket_at_the_start_is_part_of_the_class() {
+        assert_eq!(class("]a]", ']'), Some((true, String::new())));
+        assert!(matches("[]]", "]"));
+    }
+
+    #[test]
+    fn dash_at_the_end_is_literal() {
+        assert_eq!(class("a-]", '-'), Some((true, String::new())));
+        assert_eq!(class("a-]", 'b'), Some((false, String::new())));
+    }
+}
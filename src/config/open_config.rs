@@ -1,51 +1,277 @@
 use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use log::*;
 use mime::Mime;
 use rayon::prelude::*;
 use regex::Regex;
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 
-use super::load_to_string;
+use super::{find_local_config, include, layer_paths, load_to_string, ConfigPath};
+use crate::chooser::Chooser;
+use crate::command::{Command, RunOptions};
 use crate::mime_helpers::*;
+use crate::target::UrlProbe;
 
-type PossibleStrings = HashMap<String, String>;
+type PossibleStrings = HashMap<String, Command>;
+
+/// Returns why a pattern is invalid, None if it is valid
+type Validate = fn(&str) -> Option<String>;
 
 /// What the config will serialize into at first. This will then be converted into `OpenConfig` to
 /// use `Mime`s instead of `String`s.
 #[derive(Debug, Default, Deserialize)]
+#[serde(default)]
 struct OpenConfigString {
     open: Vec<PossibleStrings>,
     open_regex: Vec<PossibleStrings>,
     preview: Vec<PossibleStrings>,
     preview_regex: Vec<PossibleStrings>,
+    chooser: Option<String>,
+    /// Whether commands are detached unless their rule says otherwise
+    detach: Option<bool>,
+    /// The file that the output of detached commands is appended to
+    detach_log: Option<String>,
+    /// The shell command that runs a command in a new terminal, like `alacritty -e`
+    terminal_command: Option<String>,
+    /// The command that finds the mime type of an http URL, like `curl -sIL`
+    url_probe: Option<String>,
+    /// The file the mimes found by `url_probe` are cached in
+    url_probe_cache: Option<String>,
+    /// How many seconds a mime found by `url_probe` is cached for, 0 to not cache them
+    url_probe_ttl: Option<u64>,
+    /// The ways to find the mime type of a file, in the order they are tried
+    detection: Option<Vec<Detector>>,
+    /// Whether to warn when the extension of a file does not match its contents
+    warn_mismatch: Option<bool>,
+    /// Other config files whose groups are used as fall backs for the groups in this one
+    include: Vec<String>,
+    /// The directories whose `.opener.toml` is used, only read from the system and user configs
+    trusted_dirs: Vec<String>,
 }
 
 impl OpenConfigString {
     /// Gets the config strings and then deserializes it into `OpenConfigString`
-    fn load() -> Result<Self> {
-        let cfg_string = load_to_string()?;
-        let toml: Self = toml::from_str(&cfg_string)?;
+    fn load(path: &Path) -> Result<Self> {
+        let cfg_string = load_to_string(path)?;
+        OpenConfigString::parse(path, &cfg_string)
+    }
+
+    /// Deserializes the contents of the config file at the path
+    fn parse(path: &Path, cfg_string: &str) -> Result<Self> {
+        let toml: Self = toml::from_str(cfg_string)
+            .context(format!("Failed to parse the config file {}", path.display()))?;
         Ok(toml)
     }
 
-    /// Converts `OpenConfigString` into `OpenConfig`
-    fn convert(self) -> OpenConfig {
-        let OpenConfigString {
-            open,
-            open_regex,
-            preview,
-            preview_regex,
-        } = self;
-        let open = PossibleMimes::new_vec(open);
-        let preview = PossibleMimes::new_vec(preview);
-        OpenConfig {
-            open,
-            open_regex,
-            preview,
-            preview_regex,
+    /// Loads every layer of the config, from the most important to the least important. The
+    /// directory config closest to `dir` is only loaded if the system or user config trusts the
+    /// directory it is in.
+    fn load_layers(config_path: &ConfigPath, dir: Option<&Path>) -> Result<Vec<(PathBuf, Self)>> {
+        let mut layers = Vec::new();
+        for path in layer_paths(config_path) {
+            debug!("Loading config layer {}", path.display());
+            let config = OpenConfigString::load(&path)?;
+            // the user config does not have to exist
+            let path = path.canonicalize().unwrap_or(path);
+            OpenConfigString::add_layer(path, config, &mut Vec::new(), &mut layers)?;
+        }
+
+        if let Some(dir) = dir.filter(|_| !config_path.is_explicit()) {
+            let mut trusted = Vec::new();
+            for (_, layer) in &layers {
+                for dir in &layer.trusted_dirs {
+                    // directories that do not exist cannot have a config
+                    if let Ok(dir) = include::expand_tilde(dir)?.canonicalize() {
+                        trusted.push(dir);
+                    }
+                }
+            }
+            let local = find_local_config(dir, &trusted)
+                .filter(|local| !layers.iter().any(|(path, _)| path == local));
+            if let Some(local) = local {
+                debug!("Loading directory config {}", local.display());
+                let config = OpenConfigString::load(&local)?;
+                if !config.trusted_dirs.is_empty() {
+                    info!("trusted_dirs in {} is ignored", local.display());
+                }
+                OpenConfigString::add_layer(local, config, &mut Vec::new(), &mut layers)?;
+            }
+        }
+
+        layers.reverse();
+        Ok(layers)
+    }
+
+    /// Adds the files the config includes and then the config itself to `layers`, which go from
+    /// the least important to the most important. Later includes are more important than earlier
+    /// ones and the config is more important than all of them. `including` is the chain of
+    /// configs that included this one, used to find cycles. A file that was already added is
+    /// skipped so its groups are not repeated.
+    fn add_layer(
+        path: PathBuf,
+        config: Self,
+        including: &mut Vec<PathBuf>,
+        layers: &mut Vec<(PathBuf, Self)>,
+    ) -> Result<()> {
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let mut included = Vec::new();
+        for pattern in &config.include {
+            let paths = include::resolve(pattern, dir)?;
+            if paths.is_empty() {
+                debug!("The include {} in {} matched nothing", pattern, path.display());
+            }
+            included.extend(paths);
+        }
+
+        including.push(path.clone());
+        for include in included {
+            // canonicalize so that the same file is recognized no matter how it was named
+            let include = include.canonicalize().context(format!(
+                "Failed to find the config file {} included by {}",
+                include.display(),
+                path.display()
+            ))?;
+            if including.contains(&include) {
+                let chain: Vec<String> = including
+                    .iter()
+                    .chain(std::iter::once(&include))
+                    .map(|path| path.display().to_string())
+                    .collect();
+                bail!("The config files include each other: {}", chain.join(" -> "));
+            }
+            if layers.iter().any(|(layer, _)| *layer == include) {
+                debug!("{} was already included, skipping it", include.display());
+                continue;
+            }
+
+            debug!("Loading config {} included by {}", include.display(), path.display());
+            let source = fs::read_to_string(&include).context(format!(
+                "Failed to read the config file {} included by {}",
+                include.display(),
+                path.display()
+            ))?;
+            let config = OpenConfigString::parse(&include, &source)?;
+            OpenConfigString::add_layer(include, config, including, layers)?;
         }
+        including.pop();
+
+        layers.push((path, config));
+        Ok(())
+    }
+
+    /// Every rule in every group, including the ones that are invalid. `offsets` are the number
+    /// of groups of each section in the layers before this one, so the group numbers continue
+    /// where they left off.
+    fn rules(&self, layer: &str, offsets: &mut HashMap<&'static str, usize>) -> Vec<RuleEntry> {
+        let sections: [(&str, &Vec<PossibleStrings>, Validate); 4] = [
+            ("open", &self.open, mime_error),
+            ("open_regex", &self.open_regex, regex_error),
+            ("preview", &self.preview, mime_error),
+            ("preview_regex", &self.preview_regex, regex_error),
+        ];
+
+        let mut rules = Vec::new();
+        for (section, groups, validate) in sections.iter() {
+            let offset = offsets.entry(section).or_insert(0);
+            for (group, possible) in groups.iter().enumerate() {
+                let mut patterns: Vec<&String> = possible.keys().collect();
+                patterns.sort();
+                for pattern in patterns {
+                    rules.push(RuleEntry {
+                        section: section.to_string(),
+                        group: *offset + group,
+                        pattern: pattern.clone(),
+                        command: possible[pattern].to_string(),
+                        error: validate(pattern),
+                        layer: layer.to_string(),
+                    });
+                }
+            }
+            *offset += groups.len();
+        }
+        rules
+    }
+
+    /// Converts the layers, from the most important to the least important, into one
+    /// `OpenConfig`. The groups of more important layers go before the groups of the less
+    /// important ones, so the groups they replace become fall backs. Settings like `chooser` come
+    /// from the most important layer that has them.
+    fn convert(layers: Vec<(PathBuf, Self)>) -> Result<OpenConfig> {
+        let mut config = OpenConfig {
+            open: Vec::new(),
+            open_regex: Vec::new(),
+            preview: Vec::new(),
+            preview_regex: Vec::new(),
+            chooser: Chooser::Prompt,
+            run_options: RunOptions::default(),
+            url_probe: None,
+            detection: Detection::default(),
+        };
+        let mut chooser = None;
+        let mut detach = None;
+        let mut detach_log = None;
+        let mut terminal_command = None;
+        let mut url_probe = None;
+        let mut url_probe_cache = None;
+        let mut url_probe_ttl = None;
+        let mut detection = None;
+        let mut warn_mismatch = None;
+        for (path, layer) in layers {
+            let OpenConfigString {
+                open,
+                open_regex,
+                preview,
+                preview_regex,
+                chooser: layer_chooser,
+                detach: layer_detach,
+                detach_log: layer_detach_log,
+                terminal_command: layer_terminal_command,
+                url_probe: layer_url_probe,
+                url_probe_cache: layer_url_probe_cache,
+                url_probe_ttl: layer_url_probe_ttl,
+                detection: layer_detection,
+                warn_mismatch: layer_warn_mismatch,
+                ..
+            } = layer;
+            let name = path.display().to_string();
+            config.open.extend(PossibleMimes::new_vec(open, &name));
+            config.open_regex.extend(PossibleRegexes::new_vec(open_regex, &name));
+            config.preview.extend(PossibleMimes::new_vec(preview, &name));
+            config.preview_regex.extend(PossibleRegexes::new_vec(preview_regex, &name));
+            chooser = chooser.or(layer_chooser);
+            detach = detach.or(layer_detach);
+            detach_log = detach_log.or(layer_detach_log);
+            terminal_command = terminal_command.or(layer_terminal_command);
+            url_probe = url_probe.or(layer_url_probe);
+            url_probe_cache = url_probe_cache.or(layer_url_probe_cache);
+            url_probe_ttl = url_probe_ttl.or(layer_url_probe_ttl);
+            if layer_detection.as_ref().is_some_and(Vec::is_empty) {
+                bail!("detection in {} needs at least one way to find mimes", name);
+            }
+            detection = detection.or(layer_detection);
+            warn_mismatch = warn_mismatch.or(layer_warn_mismatch);
+        }
+        config.chooser = Chooser::new(chooser);
+        config.run_options = RunOptions {
+            detach: detach.unwrap_or(false),
+            detach_log: detach_log.map(|log| include::expand_tilde(&log)).transpose()?,
+            terminal_command,
+            preview: false,
+        };
+        let url_probe_cache = url_probe_cache
+            .map(|cache| include::expand_tilde(&cache))
+            .transpose()?;
+        config.url_probe = url_probe
+            .map(|command| UrlProbe::new(command, url_probe_cache, url_probe_ttl));
+        let default = Detection::default();
+        config.detection = Detection {
+            order: detection.unwrap_or(default.order),
+            warn_mismatch: warn_mismatch.unwrap_or(default.warn_mismatch),
+        };
+        Ok(config)
     }
 }
 
@@ -56,32 +282,89 @@ impl OpenConfigString {
 #[derive(Debug)]
 pub struct OpenConfig {
     pub open: Vec<PossibleMimes>,
-    pub open_regex: Vec<PossibleStrings>,
+    pub open_regex: Vec<PossibleRegexes>,
     pub preview: Vec<PossibleMimes>,
-    pub preview_regex: Vec<PossibleStrings>,
+    pub preview_regex: Vec<PossibleRegexes>,
+    pub chooser: Chooser,
+    /// The settings for running every command
+    pub run_options: RunOptions,
+    /// The command that finds the mime type of an http URL from its Content-Type and where its
+    /// results are cached
+    pub url_probe: Option<UrlProbe>,
+    /// How the mime types of files are found
+    pub detection: Detection,
 }
 
 impl OpenConfig {
-    /// Loads the config
-    pub fn load() -> Result<Self> {
-        Ok(OpenConfigString::load()?.convert())
+    /// Loads the user config at the path merged with the system configs and the trusted
+    /// directory config closest to `dir`, which is usually the directory of the file being opened.
+    /// A config given with --config is loaded by itself.
+    pub fn load(config_path: &ConfigPath, dir: Option<&Path>) -> Result<Self> {
+        OpenConfigString::convert(OpenConfigString::load_layers(config_path, dir)?)
     }
+
+    /// Loads every rule in every layer without discarding the invalid ones like `load` does
+    pub fn load_rules(config_path: &ConfigPath, dir: Option<&Path>) -> Result<Vec<RuleEntry>> {
+        let mut offsets = HashMap::new();
+        Ok(OpenConfigString::load_layers(config_path, dir)?
+            .iter()
+            .flat_map(|(path, config)| config.rules(&path.display().to_string(), &mut offsets))
+            .collect())
+    }
+}
+
+/// A single rule in the config and whether it is valid. Used to show the user what is in the
+/// config.
+#[derive(Debug, Serialize)]
+pub struct RuleEntry {
+    /// The array of tables the rule is in, like open or preview_regex
+    pub section: String,
+    /// The index of the table in the array
+    pub group: usize,
+    /// The mime or regex
+    pub pattern: String,
+    pub command: String,
+    /// Why the rule is invalid, None if it is valid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The path of the config file the rule came from
+    #[serde(rename = "source")]
+    pub layer: String,
+}
+
+/// Returns the error if the string is not a valid mime
+fn mime_error(s: &str) -> Option<String> {
+    s.parse::<Mime>().err().map(|e| e.to_string())
+}
+
+/// Returns the error if the string is not a valid regex. Only the last line of the error is used
+/// because the others point at the pattern.
+fn regex_error(s: &str) -> Option<String> {
+    Regex::new(s).err().map(|e| {
+        let message = e.to_string();
+        let last = message.lines().last().unwrap_or_default();
+        last.trim_start_matches("error: ").to_string()
+    })
 }
 
 /// The possible mimes and commands that can be used to open a file
 #[derive(Debug)]
-pub struct PossibleMimes(HashMap<Mime, String>);
+pub struct PossibleMimes {
+    rules: HashMap<Mime, Command>,
+    /// The path of the config file the group came from
+    layer: String,
+}
 
 impl PossibleMimes {
     /// Converts a hashmap of mime strings and commands into a hashmap of mimes and commands. This
     /// function will log the errors using warn! and then discard them.
-    pub fn new(map: PossibleStrings) -> PossibleMimes {
-        let converted: HashMap<Mime, String> = map
+    pub fn new(map: PossibleStrings, layer: &str) -> PossibleMimes {
+        let converted: HashMap<Mime, Command> = map
             .into_par_iter()
             .map(|(mime_str, command)| {
                 let mime: Result<Mime> = mime_str.parse().context(format!(
-                    "Failed to parse mime type from string {}",
-                    mime_str
+                    "Failed to parse mime type from string {} in {}",
+                    mime_str, layer
                 ));
                 mime.map(|m| (m, command))
             })
@@ -94,109 +377,164 @@ impl PossibleMimes {
             // then ignore errors
             .filter_map(|e| e.ok())
             .collect();
-        debug!("mime_strs were parsed into mime_types: {:?}", converted);
+        debug!(
+            "mime_strs from {} were parsed into mime_types: {:?}",
+            layer, converted
+        );
 
-        PossibleMimes(converted)
+        PossibleMimes {
+            rules: converted,
+            layer: layer.to_string(),
+        }
     }
 
     /// Creates a new vector of possibles. The first possible is the main one used while the other
     /// ones are just fall backs
-    pub fn new_vec(map: Vec<PossibleStrings>) -> Vec<PossibleMimes> {
+    pub fn new_vec(map: Vec<PossibleStrings>, layer: &str) -> Vec<PossibleMimes> {
         map.into_par_iter()
-            .map(|hashmap| PossibleMimes::new(hashmap))
+            .map(|possible| PossibleMimes::new(possible, layer))
             .collect()
     }
 
-    /// Narrows down the possible commands to one according to the mime type given. Then returns the
-    /// proper command that was narrowed down.
-    pub fn narrow(mut self, mime: &Mime) -> String {
-        // first filter them so that only mimes that are equal are kept, including star mimes.
-        // application/* == application/pdf is true
-        self = self.filter_equal(mime);
-        debug!("Matches before narrowing down to 1: {:?}", self);
-
-        if self.matches_not_one() {
-            // if there are still more matches, remove the star mimes because each mime type can
-            // only have two possible matches, the specific and star. For example application/pdf
-            // only has two matches, application/pdf and application/*. If star is removed, the
-            // command for application/pdf is left.
-            self = self.remove_star_mimes();
-        }
-        debug!("Matches after narrowing down to 1: {:?}", self);
-
-        // there should only be one match left
-        if self.matches_not_one() {
-            panic!("BUG: matches length should not be greater than 1. Toml file should have non-repeating strings. After removing stars there can only be one match for each mime type.")
-        }
-
-        // there is only one match left so this just returns the command associated with it.
-        self.0.into_iter().map(|(_mime, command)| command).collect()
+    /// Every rule in the group, sorted by the mime
+    pub fn rules(&self) -> Vec<(&Mime, &Command)> {
+        let mut rules: Vec<(&Mime, &Command)> = self.rules.iter().collect();
+        rules.sort_by(|(m1, _), (m2, _)| m1.as_ref().cmp(m2.as_ref()));
+        rules
     }
 
-    fn filter_equal(self, mime_match: &Mime) -> Self {
-        let map: HashMap<Mime, String> = self
-            .0
-            .into_par_iter()
-            .filter(|(mime, _command)| mime_equal(mime_match, mime))
+    /// Every rule that matches the mime with how well it matches, from the best match to the
+    /// worst. Rules that match equally well are sorted by the mime so the order is always the same.
+    pub fn ranked(&self, mime: &Mime) -> Vec<(Specificity, &Mime, &Command)> {
+        let mut matches: Vec<(Specificity, &Mime, &Command)> = self
+            .rules
+            .iter()
+            .filter_map(|(rule, command)| {
+                specificity(mime, rule).map(|specificity| (specificity, rule, command))
+            })
             .collect();
-        PossibleMimes(map)
+        matches.sort_by(|(s1, r1, _), (s2, r2, _)| {
+            s1.cmp(s2).then_with(|| r1.as_ref().cmp(r2.as_ref()))
+        });
+        matches
     }
+}
 
-    /// Removes the mimes and commands that have star mimes like text/*
-    fn remove_star_mimes(self) -> Self {
-        let map: HashMap<Mime, String> = self
-            .0
-            .into_par_iter()
-            .filter(|(mime, _command)| {
-                mime.subtype().as_str() != "*" && mime.type_().as_str() != "*"
-            })
-            .collect();
-        PossibleMimes(map)
+impl Narrowable for PossibleMimes {
+    type Compare = Mime;
+
+    fn layer(&self) -> &str {
+        &self.layer
     }
 
-    fn matches_not_one(&self) -> bool {
-        self.0.len() > 1
+    /// Every command whose mime matches the mime given, from the most specific mime to the least
+    fn candidates(&self, mime: &Mime) -> Vec<Command> {
+        self.ranked(mime)
+            .into_iter()
+            .map(|(_specificity, _rule, command)| command.clone())
+            .collect()
     }
-}
 
-struct PossibleRegexes(HashMap<String, String>);
+    /// Narrows down the possible commands to the one with the most specific mime. For example
+    /// text/plain is used over text/* for a text/plain file. Returns None if no mime matches.
+    fn narrow(&self, mime: &Mime) -> Option<Command> {
+        let ranked = self.ranked(mime);
+        debug!("Matches ranked by specificity: {:?}", ranked);
+        ranked
+            .into_iter()
+            .next()
+            .map(|(_specificity, _rule, command)| command.clone())
+    }
+}
 
-impl Narrowable for PossibleRegexes {
-    type Compare = String;
+/// The possible regexes and commands that can be used to open a file
+#[derive(Debug)]
+pub struct PossibleRegexes {
+    rules: Vec<(Regex, Command)>,
+    /// The path of the config file the group came from
+    layer: String,
+}
 
-    /// Compare is the string filename. It is narrowing down which regex is possibleregexes matches
-    /// the filename.
-    fn narrow(self, compare: String) -> Result<String> {
-        let commands: Vec<String> = self
-            .0
+impl PossibleRegexes {
+    /// Compiles a hashmap of regex strings and commands into regexes and commands. This function
+    /// will log the errors using warn! and then discard them.
+    pub fn new(map: PossibleStrings, layer: &str) -> PossibleRegexes {
+        let mut converted: Vec<(Regex, Command)> = map
             .into_par_iter()
             .map(|(regex_string, command)| Regex::new(&regex_string).map(|regex| (regex, command)))
             .inspect(|result| {
                 if let Err(e) = result {
-                    warn!("Failed to create regex: {}", e);
+                    warn!("Failed to create regex in {}: {}", layer, e);
                 }
             })
             .filter_map(|result| result.ok())
-            .filter(|(regex, _command)| regex.is_match(&compare))
-            .map(|(_regex, command)| command)
             .collect();
-        if commands.len() > 1 {
-            Ok(choose_with_rofi(&commands)?)
-        } else {
-            Ok(commands.into_iter().collect::<String>())
+        // longer regexes are usually more specific so they go first
+        converted.sort_by(|(r1, _), (r2, _)| {
+            r2.as_str()
+                .len()
+                .cmp(&r1.as_str().len())
+                .then_with(|| r1.as_str().cmp(r2.as_str()))
+        });
+        debug!(
+            "regex_strs from {} were compiled into regexes: {:?}",
+            layer, converted
+        );
+
+        PossibleRegexes {
+            rules: converted,
+            layer: layer.to_string(),
         }
     }
+
+    /// Every rule in the group, in the order they are tried
+    pub fn rules(&self) -> impl Iterator<Item = (&Regex, &Command)> {
+        self.rules.iter().map(|(regex, command)| (regex, command))
+    }
+
+    /// Creates a new vector of possible regexes. Like `PossibleMimes::new_vec`, the first one is
+    /// the main one and the others are fall backs.
+    pub fn new_vec(map: Vec<PossibleStrings>, layer: &str) -> Vec<PossibleRegexes> {
+        map.into_par_iter()
+            .map(|possible| PossibleRegexes::new(possible, layer))
+            .collect()
+    }
 }
 
-/// Choose the command with rofi
-fn choose_with_rofi(commands: &[String]) -> Result<String> {
-    todo!()
+impl Narrowable for PossibleRegexes {
+    type Compare = str;
+
+    fn layer(&self) -> &str {
+        &self.layer
+    }
+
+    /// Every command whose regex matches the filename, longest regex first.
+    fn candidates(&self, compare: &str) -> Vec<Command> {
+        self.rules
+            .iter()
+            .filter(|(regex, _command)| regex.is_match(compare))
+            .map(|(_regex, command)| command.clone())
+            .collect()
+    }
+
+    /// Compare is the string filename. It is narrowing down which regex is possibleregexes matches
+    /// the filename. If more than one regex matches, the longest one is used.
+    fn narrow(&self, compare: &str) -> Option<Command> {
+        self.candidates(compare).into_iter().next()
+    }
 }
 
 /// Something that can be narrowed down and return a command
-trait Narrowable {
-    type Compare;
+pub trait Narrowable {
+    type Compare: ?Sized;
+
+    /// The path of the config file this came from
+    fn layer(&self) -> &str;
+
+    /// Every command that matches what is compared, from the best match to the worst
+    fn candidates(&self, compare: &Self::Compare) -> Vec<Command>;
 
-    /// Narrow down something according to what is compared against each item
-    fn narrow(self, compare: Self::Compare) -> Result<String>;
+    /// Narrow down something according to what is compared against each item. Returns None if
+    /// nothing matches.
+    fn narrow(&self, compare: &Self::Compare) -> Option<Command>;
 }
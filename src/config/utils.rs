@@ -1,63 +1,217 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::fs::OpenOptions;
 use std::io::ErrorKind;
 use std::io::{self, Read, Write};
+use std::ops::Deref;
 use std::path::{Path, PathBuf};
 use std::str;
 
 use anyhow::{anyhow, bail, Context, Result};
 use directories::ProjectDirs;
+use log::*;
 
-const DEFAULT_CONFIG: &'static [u8] = include_bytes!("default_config.toml");
-const EXTENSION: &'static str = "toml";
-const NAME: &'static str = "opener";
-const QUALIFIER: &'static str = "rs";
-const ORGANIZATION: &'static str = "";
+use crate::xdg;
+
+const DEFAULT_CONFIG: &[u8] = include_bytes!("default_config.toml");
+const EXTENSION: &str = "toml";
+const NAME: &str = "opener";
+const QUALIFIER: &str = "rs";
+const ORGANIZATION: &str = "";
+/// The name of the config that applies to a directory and everything in it
+const LOCAL_CONFIG: &str = ".opener.toml";
 
 /// Loads config into string
-pub fn load_to_string() -> Result<String> {
-    load_to_string_or_default(get_config_path()?)
+pub fn load_to_string(path: &Path) -> Result<String> {
+    load_to_string_or_default(path)
 }
 
 /// Stores string into config
-pub fn store_string(s: &str) -> Result<()> {
-    let mut f = open_file(get_config_path()?)?;
+pub fn store_string(path: &Path, s: &str) -> Result<()> {
+    let mut f = open_file(path)?;
     f.write_all(s.as_bytes())?;
     Ok(())
 }
 
-/// Opens file with the correct options
+/// The config file that opener uses. It derefs to its path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigPath {
+    path: PathBuf,
+    /// Whether it was given with --config or OPENER_CONFIG, then it is used by itself without the
+    /// system and directory configs
+    explicit: bool,
+}
+
+impl ConfigPath {
+    /// The user config in the config directory
+    pub fn user() -> Result<Self> {
+        Ok(ConfigPath {
+            path: get_config_path()?,
+            explicit: false,
+        })
+    }
+
+    /// A config file that was given by the user
+    pub fn explicit(path: PathBuf) -> Self {
+        ConfigPath {
+            path,
+            explicit: true,
+        }
+    }
+
+    pub fn is_explicit(&self) -> bool {
+        self.explicit
+    }
+}
+
+impl Deref for ConfigPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for ConfigPath {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// The paths of the system configs in `XDG_CONFIG_DIRS` and the user config, from the least
+/// important to the most important. Only the configs that exist are included. If none of them do,
+/// the user config is the only layer and the default config is used for it. A config given with
+/// --config is used by itself.
+pub fn layer_paths(config_path: &ConfigPath) -> Vec<PathBuf> {
+    layer_paths_in(config_path, &xdg::config_dirs())
+}
+
+/// `layer_paths` with the system config directories given, the most important one first
+fn layer_paths_in(config_path: &ConfigPath, config_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    if config_path.is_explicit() {
+        return vec![config_path.to_path_buf()];
+    }
+
+    let mut paths: Vec<PathBuf> = config_dirs
+        .iter()
+        .rev()
+        .map(|dir| dir.join(NAME).join(format!("{}.{}", NAME, EXTENSION)))
+        .filter(|path| path.is_file() && path != &**config_path)
+        .collect();
+
+    if config_path.exists() {
+        paths.push(config_path.to_path_buf());
+    }
+
+    // the default config would otherwise hide the system configs of users without a config
+    if paths.is_empty() {
+        paths.push(config_path.to_path_buf());
+    }
+    paths
+}
+
+/// The contents of the config that `set`, `unset` and `import` change. A config that does not
+/// exist starts from the default config only when it would be the only layer, like `layer_paths`
+/// does, otherwise it starts empty so that a copy of the default does not hide the system configs.
+pub fn load_to_edit(config_path: &ConfigPath) -> Result<String> {
+    load_to_edit_in(config_path, &xdg::config_dirs())
+}
+
+/// `load_to_edit` with the system config directories given, the most important one first
+fn load_to_edit_in(config_path: &ConfigPath, config_dirs: &[PathBuf]) -> Result<String> {
+    let layers = layer_paths_in(config_path, config_dirs);
+    if !config_path.exists() && !layers.iter().any(|layer| layer == &**config_path) {
+        info!(
+            "The config file {} does not exist, starting it empty",
+            config_path.display()
+        );
+        return Ok(String::new());
+    }
+    load_to_string(config_path)
+}
+
+/// Walks up from `dir` to find the closest directory config in one of the `trusted` directories.
+/// A directory config can run any command, so the ones in other directories, like a cloned
+/// repository, are skipped.
+pub fn find_local_config(dir: &Path, trusted: &[PathBuf]) -> Option<PathBuf> {
+    // the parent of a relative path with one component is empty
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let dir = dir.canonicalize().ok()?;
+    for dir in dir.ancestors() {
+        let path = dir.join(LOCAL_CONFIG);
+        if !path.is_file() {
+            continue;
+        }
+        if trusted.iter().any(|trusted| trusted == dir) {
+            return Some(path);
+        }
+        info!(
+            "Skipping {} because its directory is not in trusted_dirs",
+            path.display()
+        );
+    }
+    None
+}
+
+/// Opens file with the correct options, creating the directories it is in if needed
 fn open_file(path: impl AsRef<Path>) -> Result<File> {
-    Ok(OpenOptions::new()
+    if let Some(dir) = path.as_ref().parent() {
+        fs::create_dir_all(dir).context("Failed to create the config directory.")?;
+    }
+    OpenOptions::new()
         .write(true)
         .create(true)
         .truncate(true)
         .open(path)
-        .context("Failed to open file.")?)
+        .context("Failed to open file.")
 }
 
-/// Stores the default config in the specified path.
-fn store_default(path: impl AsRef<Path>) -> Result<&'static str> {
-    let mut f = open_file(path)?;
+/// The default config that is embedded in the binary
+pub fn default_config() -> &'static str {
+    str::from_utf8(DEFAULT_CONFIG).expect("BUG: the default config was not utf8")
+}
+
+/// Stores the default config in the config path. Fails if there is already a config unless `force`
+/// is true.
+pub fn store_default(path: &Path, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        bail!(
+            "The config file {} already exists, use --force to overwrite it",
+            path.display()
+        );
+    }
 
+    let mut f = open_file(path)?;
     f.write_all(DEFAULT_CONFIG)
         .context("Failed to write to default config")?;
 
-    Ok(str::from_utf8(DEFAULT_CONFIG).expect("BUG: the default config was not utf8"))
+    Ok(())
 }
 
-/// Loads a file to string or creates a default if it does not exist, then returns the default
-/// string
+/// Loads a file to string or returns the default config if it does not exist. The default is not
+/// written to the file, that is done by opener init.
 fn load_to_string_or_default(path: impl AsRef<Path>) -> Result<String> {
     match File::open(&path) {
         Ok(mut file) => Ok(file.get_string()?),
-        Err(e) if e.kind() == ErrorKind::NotFound => Ok(store_default(&path)?.to_string()),
-        Err(_e) => bail!("General load error"),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            info!(
+                "The config file {} does not exist, using the default config",
+                path.as_ref().display()
+            );
+            Ok(default_config().to_string())
+        }
+        Err(e) => Err(e).context(format!(
+            "Failed to load the config file {}",
+            path.as_ref().display()
+        )),
     }
 }
 
 /// Gets the path of the config file
-fn get_config_path() -> Result<PathBuf> {
+pub fn get_config_path() -> Result<PathBuf> {
     let project =
         ProjectDirs::from(QUALIFIER, ORGANIZATION, NAME).ok_or(anyhow!("An error occured"))?;
 
@@ -90,3 +244,83 @@ impl CheckedStringRead for File {
         Ok(s)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::Command;
+    use crate::config::EditConfig;
+    use std::env;
+    use std::process;
+
+    /// An empty directory for a test to keep its configs in
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("opener-utils-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A system config in `dir` with a rule for images, returns the config directories and the
+    /// path of the config
+    fn system_config(dir: &Path) -> (Vec<PathBuf>, PathBuf) {
+        let config_dir = dir.join("xdg");
+        let path = config_dir.join(NAME).join("opener.toml");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "[[open]]\n'image/*' = 'sysviewer'\n").unwrap();
+        (vec![config_dir], path)
+    }
+
+    fn user_config(dir: &Path) -> ConfigPath {
+        ConfigPath {
+            path: dir.join("user").join("opener.toml"),
+            explicit: false,
+        }
+    }
+
+    #[test]
+    fn missing_user_config_is_not_a_layer_when_there_is_a_system_config() {
+        let dir = test_dir("layers");
+        let (config_dirs, system) = system_config(&dir);
+        let user = user_config(&dir);
+        assert_eq!(layer_paths_in(&user, &config_dirs), [system]);
+        assert_eq!(layer_paths_in(&user, &[]), [user.to_path_buf()]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn system_layer_survives_the_first_set() {
+        let dir = test_dir("first-set");
+        let (config_dirs, system) = system_config(&dir);
+        let user = user_config(&dir);
+
+        let source = load_to_edit_in(&user, &config_dirs).unwrap();
+        let mut cfg = EditConfig::parse(&user, &source).unwrap();
+        let command = Command::parse("myviewer").unwrap();
+        assert!(cfg.set(false, "image/png", &command).unwrap());
+        cfg.store().unwrap();
+
+        let stored = fs::read_to_string(&*user).unwrap();
+        assert_eq!(stored.trim(), "[[open]]\n\"image/png\" = \"myviewer\"");
+        assert_eq!(
+            layer_paths_in(&user, &config_dirs),
+            [system, user.to_path_buf()]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_config_starts_from_the_default_when_it_is_the_only_layer() {
+        let dir = test_dir("default");
+        let user = user_config(&dir);
+        assert_eq!(load_to_edit_in(&user, &[]).unwrap(), default_config());
+
+        let explicit = ConfigPath::explicit(dir.join("explicit.toml"));
+        let (config_dirs, _) = system_config(&dir);
+        assert_eq!(
+            load_to_edit_in(&explicit, &config_dirs).unwrap(),
+            default_config()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
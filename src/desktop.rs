@@ -0,0 +1,650 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use directories::BaseDirs;
+
+use log::*;
+
+use crate::command::{
+    execute, file_url, in_terminal, is_executable, join, path_args, quote, Command, Detached,
+    RunOptions, StructuredCommand,
+};
+use crate::target::is_uri;
+use crate::xdg;
+
+/// The group of a desktop file that describes the application
+const DESKTOP_ENTRY: &str = "Desktop Entry";
+
+/// The groups of a key file like a desktop file or mimeapps.list, in the order they are in the
+/// file. Each group has its keys and values in order.
+pub type Groups = Vec<(String, Vec<(String, String)>)>;
+
+/// Parses a key file, the ini-like format used by desktop files and mimeapps.list. Comments,
+/// blank lines and lines that are not keys are ignored. The escapes in the values are replaced.
+pub fn parse_key_file(source: &str) -> Groups {
+    let mut groups: Groups = Vec::new();
+    for line in source.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            groups.push((name.to_string(), Vec::new()));
+        } else if let (Some((key, value)), Some((_, entries))) =
+            (line.split_once('='), groups.last_mut())
+        {
+            entries.push((key.trim().to_string(), unescape(value.trim())));
+        }
+    }
+    groups
+}
+
+/// Replaces the escapes that the desktop entry spec allows in values
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => unescaped.push(' '),
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('r') => unescaped.push('\r'),
+            Some('\\') => unescaped.push('\\'),
+            // other escapes are kept for the list or exec parsing that comes after this
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// The directories that desktop files are searched for in, the most important one first
+pub fn applications_dirs() -> Vec<PathBuf> {
+    xdg::data_dirs()
+        .into_iter()
+        .map(|dir| dir.join("applications"))
+        .collect()
+}
+
+/// The applications directory of the user, where desktop files are written to
+pub fn user_applications_dir() -> Result<PathBuf> {
+    let base = BaseDirs::new().ok_or_else(|| anyhow!("Failed to find the data directory"))?;
+    Ok(base.data_dir().join("applications"))
+}
+
+/// Finds the desktop file with the desktop file id, like `org.pwmt.zathura.desktop`. Like the spec
+/// says, the dashes in the id can also be directories, so `kde-okular.desktop` can be
+/// `kde/okular.desktop`.
+pub fn find(id: &str) -> Option<PathBuf> {
+    applications_dirs()
+        .into_iter()
+        .find_map(|dir| find_in(&dir, id))
+}
+
+/// Finds the desktop file id in one applications directory
+fn find_in(dir: &Path, id: &str) -> Option<PathBuf> {
+    let path = dir.join(id);
+    if path.is_file() {
+        return Some(path);
+    }
+    id.match_indices('-').find_map(|(i, _)| {
+        let subdir = dir.join(&id[..i]);
+        if subdir.is_dir() {
+            find_in(&subdir, &id[i + 1..])
+        } else {
+            None
+        }
+    })
+}
+
+/// The parts of a desktop file that are needed to run the application
+#[derive(Debug, Clone)]
+pub struct DesktopEntry {
+    /// Where the desktop file is
+    pub path: PathBuf,
+    pub name: Option<String>,
+    pub exec: Option<String>,
+    pub try_exec: Option<String>,
+    pub icon: Option<String>,
+    /// The directory to run the application in
+    pub working_dir: Option<String>,
+    /// Whether the application has to be run in a terminal
+    pub terminal: bool,
+}
+
+impl DesktopEntry {
+    /// Loads the desktop file with the desktop file id
+    pub fn find(id: &str) -> Result<DesktopEntry> {
+        let path = find(id).ok_or_else(|| {
+            anyhow!(
+                "Failed to find the desktop file {} in the applications directories",
+                id
+            )
+        })?;
+        DesktopEntry::load(&path)
+    }
+
+    /// Loads the desktop file at the path
+    pub fn load(path: &Path) -> Result<DesktopEntry> {
+        let source = fs::read_to_string(path)
+            .context(format!("Failed to read the desktop file {}", path.display()))?;
+        Ok(DesktopEntry::parse(path, &source))
+    }
+
+    /// Parses the desktop entry group of a desktop file. Localized keys like `Name[de]` are
+    /// ignored.
+    pub fn parse(path: &Path, source: &str) -> DesktopEntry {
+        let mut entry = DesktopEntry {
+            path: path.to_path_buf(),
+            name: None,
+            exec: None,
+            try_exec: None,
+            icon: None,
+            working_dir: None,
+            terminal: false,
+        };
+
+        let groups = parse_key_file(source);
+        let keys = groups
+            .into_iter()
+            .find(|(name, _)| name == DESKTOP_ENTRY)
+            .map(|(_, keys)| keys)
+            .unwrap_or_default();
+        for (key, value) in keys {
+            match key.as_str() {
+                "Name" => entry.name = Some(value),
+                "Exec" => entry.exec = Some(value),
+                "TryExec" => entry.try_exec = Some(value),
+                "Icon" => entry.icon = Some(value),
+                "Path" => entry.working_dir = Some(value),
+                "Terminal" => entry.terminal = value == "true",
+                _ => (),
+            }
+        }
+        entry
+    }
+
+    /// The arguments of the Exec key with the quoting removed. The field codes are left in.
+    pub fn exec_args(&self) -> Result<Vec<String>> {
+        let exec = self
+            .exec
+            .as_deref()
+            .ok_or_else(|| anyhow!("The desktop file {} has no Exec key", self.path.display()))?;
+        split_exec(exec).context(format!(
+            "The Exec key of the desktop file {} is invalid",
+            self.path.display()
+        ))
+    }
+
+    /// The arguments to run the application with for the paths. An application that only takes
+    /// one file or url at a time is run once for each path, so there is one list of arguments for
+    /// each time it has to be run. Applications with `Terminal=true` are wrapped to run in a
+    /// terminal like `launch` runs them. Relative paths are made absolute when the application
+    /// runs in the directory of its `Path` key, so that they still point at the same files.
+    pub fn command_lines(&self, paths: &[&Path], options: &RunOptions) -> Result<Vec<Vec<OsString>>> {
+        let args = self.exec_args()?;
+        if args.is_empty() {
+            bail!("The Exec key of the desktop file {} is empty", self.path.display());
+        }
+        let paths: Vec<PathBuf> = paths
+            .iter()
+            .map(|path| match &self.working_dir {
+                Some(_) if !is_uri(path) => std::path::absolute(path)
+                    .context(format!("Failed to find the absolute path of {}", path.display())),
+                _ => Ok(path.to_path_buf()),
+            })
+            .collect::<Result<_>>()?;
+        let paths: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+        let invocations = if !takes_many(&args) {
+            paths
+                .iter()
+                .map(|path| self.expand_args(&args, &[path]))
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            vec![self.expand_args(&args, &paths)?]
+        };
+        Ok(invocations
+            .into_iter()
+            .map(|args| if self.terminal { in_terminal(args, options) } else { args })
+            .collect())
+    }
+
+    /// Whether the application can open many files at once, otherwise it is run once for each
+    pub fn takes_many(&self) -> Result<bool> {
+        Ok(takes_many(&self.exec_args()?))
+    }
+
+    /// Replaces the field codes in the arguments of the Exec key. Paths that are not valid UTF-8
+    /// are kept as they are.
+    fn expand_args(&self, args: &[String], paths: &[&Path]) -> Result<Vec<OsString>> {
+        let urls = paths
+            .iter()
+            .map(|path| file_url(path))
+            .collect::<Result<Vec<_>>>()?;
+        // relative paths that start with - are not options
+        let paths = path_args(paths);
+
+        let mut expanded = Vec::new();
+        for arg in args {
+            // the field codes for many files or the icon have to be arguments by themselves
+            match arg.as_str() {
+                "%F" => expanded.extend(paths.iter().cloned()),
+                "%U" => expanded.extend(urls.iter().map(OsString::from)),
+                "%i" => {
+                    if let Some(icon) = &self.icon {
+                        expanded.push("--icon".into());
+                        expanded.push(icon.into());
+                    }
+                }
+                _ => {
+                    let mut word = OsString::new();
+                    let mut literal = String::new();
+                    let mut chars = arg.chars();
+                    while let Some(c) = chars.next() {
+                        if c != '%' {
+                            literal.push(c);
+                            continue;
+                        }
+                        word.push(&literal);
+                        literal.clear();
+                        match chars.next() {
+                            Some('f') => word.extend(paths.first().cloned()),
+                            Some('F') => word.push(join(&paths, " ")),
+                            Some('u') => word.push(urls.first().map_or("", String::as_str)),
+                            Some('U') => word.push(urls.join(" ")),
+                            Some('c') => word.push(self.name.as_deref().unwrap_or_default()),
+                            Some('k') => word.push(&self.path),
+                            Some('%') => literal.push('%'),
+                            // deprecated and unknown field codes are removed
+                            _ => (),
+                        }
+                    }
+                    word.push(literal);
+                    // a field code with nothing to replace it with is removed, like the spec says
+                    if !word.is_empty() || !arg.starts_with('%') {
+                        expanded.push(word);
+                    }
+                }
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// Fails if the program in TryExec is not installed
+    pub fn check_installed(&self) -> Result<()> {
+        match &self.try_exec {
+            Some(try_exec) if !is_executable(try_exec) => bail!(
+                "The program {} of the desktop file {} is not installed",
+                try_exec,
+                self.path.display()
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs the application with the paths and waits for it to exit, unless it is detached. Fails
+    /// if the program in TryExec is not installed or the application exits with an error. The
+    /// programs it detached are returned for `check_detached`.
+    pub fn launch(&self, paths: &[&Path], options: &RunOptions) -> Result<Vec<Detached>> {
+        self.check_installed()?;
+
+        let detach = options.detaches(None, self.terminal);
+        let mut detached = Vec::new();
+        for args in self.command_lines(paths, options)? {
+            debug!("Running {:?} from the desktop file {}", args, self.path.display());
+            let dir = self.working_dir.as_deref().map(Path::new);
+            let failure = format!(
+                "The application {} from the desktop file {} failed",
+                args[0].to_string_lossy(),
+                self.path.display()
+            );
+            let launch = execute(&args, &BTreeMap::new(), dir, detach, options)
+                .context(format!("Failed to run the application from {}", self.path.display()))?;
+            detached.extend(launch.check(failure)?);
+        }
+
+        Ok(detached)
+    }
+
+    /// Converts the desktop entry into an opener command. An entry with `Terminal=true` or a
+    /// `Path` key becomes a command table that keeps them, the others become shell commands.
+    pub fn to_command(&self) -> Result<Command> {
+        let cmd = self.to_shell_command()?;
+        if !self.terminal && self.working_dir.is_none() {
+            return Ok(Command::Shell(cmd));
+        }
+        Ok(Command::Structured(StructuredCommand {
+            cmd,
+            args: Vec::new(),
+            terminal: self.terminal,
+            detach: None,
+            env: BTreeMap::new(),
+            cwd: self.working_dir.clone(),
+            shell: true,
+        }))
+    }
+
+    /// Converts the Exec key into a shell command. The field codes for files and urls become
+    /// placeholders and the others are replaced with what they stand for.
+    fn to_shell_command(&self) -> Result<String> {
+        let mut words = Vec::new();
+        for arg in self.exec_args()? {
+            match arg.as_str() {
+                // the icon is two words, or nothing if there is no icon
+                "%i" => {
+                    if let Some(icon) = &self.icon {
+                        words.push(format!("--icon {}", quote(icon)));
+                    }
+                    continue;
+                }
+                // there is no placeholder for the urls of every file, but applications that
+                // take urls also take paths
+                "%F" | "%U" => {
+                    words.push("%F".to_string());
+                    continue;
+                }
+                _ => (),
+            }
+
+            let mut word = String::new();
+            let mut literal = String::new();
+            let mut chars = arg.chars();
+            while let Some(c) = chars.next() {
+                if c != '%' {
+                    literal.push(c);
+                    continue;
+                }
+                let placeholder = match chars.next() {
+                    Some('f') => "%f",
+                    Some('u') => "%u",
+                    Some('%') => {
+                        // a literal % has to be escaped for opener too
+                        literal.push_str("%%");
+                        continue;
+                    }
+                    Some('c') => {
+                        literal.push_str(&self.name.as_deref().unwrap_or_default().replace('%', "%%"));
+                        continue;
+                    }
+                    Some('k') => {
+                        literal.push_str(&self.path.to_string_lossy().replace('%', "%%"));
+                        continue;
+                    }
+                    // deprecated and unknown field codes are removed
+                    _ => continue,
+                };
+                if !literal.is_empty() {
+                    word.push_str(&quote(&literal));
+                    literal.clear();
+                }
+                word.push_str(placeholder);
+            }
+            if !literal.is_empty() {
+                word.push_str(&quote(&literal));
+            }
+            if !word.is_empty() {
+                words.push(word);
+            }
+        }
+
+        if words.is_empty() {
+            bail!("The Exec key of the desktop file {} is empty", self.path.display());
+        }
+        Ok(words.join(" "))
+    }
+}
+
+/// Whether the arguments of an Exec key take many files, which they do unless they only have
+/// field codes for one file
+fn takes_many(args: &[String]) -> bool {
+    let many = args.iter().any(|arg| arg.contains("%F") || arg.contains("%U"));
+    let one = args.iter().any(|arg| arg.contains("%f") || arg.contains("%u"));
+    many || !one
+}
+
+/// Splits the Exec key into arguments. Arguments can be quoted with double quotes, where `"`, `` `
+/// ``, `$` and `\` have to be escaped with a backslash.
+fn split_exec(exec: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut chars = exec.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => arg.push(escaped),
+                            None => bail!("a backslash at the end of the Exec key"),
+                        },
+                        Some(c) => arg.push(c),
+                        None => bail!("a quote that is never closed"),
+                    }
+                }
+            }
+            c if c.is_whitespace() => {
+                if let Some(arg) = arg.take() {
+                    args.push(arg);
+                }
+            }
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+
+    Ok(args)
+}
+
+/// Quotes an argument for the Exec key so that it is not split or read as a field code
+pub fn quote_exec(arg: &str) -> String {
+    let arg = arg.replace('%', "%%");
+    let needs_quotes = arg.is_empty()
+        || arg
+            .chars()
+            .any(|c| c.is_whitespace() || "\"'\\><~|&;$*?#()`".contains(c));
+    let quoted = if needs_quotes {
+        let mut quoted = String::from("\"");
+        for c in arg.chars() {
+            if "\"`$\\".contains(c) {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        quoted.push('"');
+        quoted
+    } else {
+        arg
+    };
+    // the value of the key is escaped too, so each backslash has to be doubled again
+    quoted.replace('\\', "\\\\")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(exec: &str) -> DesktopEntry {
+        let source = format!("[Desktop Entry]\nName=Viewer\nIcon=viewer\nExec={}\n", exec);
+        DesktopEntry::parse(Path::new("/apps/viewer.desktop"), &source)
+    }
+
+    fn expand(exec: &str, paths: &[&str]) -> Vec<OsString> {
+        let entry = entry(exec);
+        let args = entry.exec_args().unwrap();
+        let paths: Vec<&Path> = paths.iter().map(Path::new).collect();
+        entry.expand_args(&args, &paths).unwrap()
+    }
+
+    #[test]
+    fn split_exec_splits_on_whitespace() {
+        assert_eq!(
+            split_exec("  viewer  -a   %f ").unwrap(),
+            ["viewer", "-a", "%f"]
+        );
+    }
+
+    #[test]
+    fn split_exec_removes_the_quotes() {
+        assert_eq!(
+            split_exec(r#""/opt/my app/run" --title="a b" """#).unwrap(),
+            ["/opt/my app/run", "--title=a b", ""]
+        );
+        assert_eq!(
+            split_exec(r#"sh -c "echo \"\$1\" \\ \`x\`""#).unwrap(),
+            ["sh", "-c", r#"echo "$1" \ `x`"#]
+        );
+    }
+
+    #[test]
+    fn split_exec_fails_on_unclosed_quotes() {
+        assert!(split_exec(r#"viewer "a b"#).is_err());
+        assert!(split_exec(r#"viewer "a\"#).is_err());
+    }
+
+    #[test]
+    fn the_value_is_unescaped_before_it_is_split() {
+        // \\ in the file is one backslash in the Exec key, which escapes the quote
+        let entry = entry(r#"viewer "a\\"b" \s"#);
+        assert_eq!(entry.exec_args().unwrap(), ["viewer", r#"a"b"#]);
+    }
+
+    #[test]
+    fn expand_args_fills_in_the_field_codes() {
+        assert_eq!(expand("viewer %f", &["a b.png"]), ["viewer", "a b.png"]);
+        assert_eq!(expand("viewer %F", &["a", "b"]), ["viewer", "a", "b"]);
+        assert_eq!(
+            expand("viewer %U", &["/tmp/a b"]),
+            ["viewer", "file:///tmp/a%20b"]
+        );
+        assert_eq!(
+            expand("viewer --name=%c %k", &["a"]),
+            ["viewer", "--name=Viewer", "/apps/viewer.desktop"]
+        );
+        assert_eq!(
+            expand("viewer %i %f", &["a"]),
+            ["viewer", "--icon", "viewer", "a"]
+        );
+    }
+
+    #[test]
+    fn paths_that_start_with_a_dash_are_not_options() {
+        assert_eq!(expand("viewer %f", &["-a"]), ["viewer", "./-a"]);
+        assert_eq!(
+            expand("viewer %F", &["-a", "/-b"]),
+            ["viewer", "./-a", "/-b"]
+        );
+        assert_eq!(
+            expand("viewer --files=%F", &["-a"]),
+            ["viewer", "--files=./-a"]
+        );
+    }
+
+    #[test]
+    fn expand_args_keeps_paths_that_are_not_utf8() {
+        use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+        let entry = entry("viewer --file=%f");
+        let path = Path::new(std::ffi::OsStr::from_bytes(b"a\xff"));
+        let args = entry
+            .expand_args(&entry.exec_args().unwrap(), &[path])
+            .unwrap();
+        let args: Vec<Vec<u8>> = args.into_iter().map(OsString::into_vec).collect();
+        assert_eq!(args, [b"viewer".to_vec(), b"--file=a\xff".to_vec()]);
+    }
+
+    #[test]
+    fn expand_args_removes_deprecated_and_empty_field_codes() {
+        assert_eq!(
+            expand("viewer %d %D %n %N %v %m %f", &["a"]),
+            ["viewer", "a"]
+        );
+        assert_eq!(expand("viewer 100%%", &["a"]), ["viewer", "100%"]);
+    }
+
+    #[test]
+    fn quote_exec_survives_parsing() {
+        for arg in [
+            "plain",
+            "a b",
+            "100%",
+            r#"it's "quoted""#,
+            r"back\slash",
+            "$HOME",
+            "",
+        ] {
+            let exec = format!("viewer {} %f", quote_exec(arg));
+            assert_eq!(
+                expand(&exec, &["file"]),
+                ["viewer", arg, "file"],
+                "{}",
+                exec
+            );
+        }
+    }
+
+    #[test]
+    fn relative_paths_are_made_absolute_for_entries_with_a_working_dir() {
+        let source = "[Desktop Entry]\nName=Viewer\nExec=viewer %F\nPath=/srv\n";
+        let in_dir = DesktopEntry::parse(Path::new("/apps/viewer.desktop"), source);
+        let paths = [
+            Path::new("doc.pdf"),
+            Path::new("/tmp/a.pdf"),
+            Path::new("https://example.com"),
+        ];
+        let lines = in_dir
+            .command_lines(&paths, &RunOptions::default())
+            .unwrap();
+        let doc = std::env::current_dir().unwrap().join("doc.pdf");
+        assert_eq!(
+            lines,
+            [[
+                "viewer",
+                &doc.to_string_lossy(),
+                "/tmp/a.pdf",
+                "https://example.com"
+            ]]
+        );
+
+        let lines = entry("viewer %F").command_lines(&paths[..1], &RunOptions::default());
+        assert_eq!(lines.unwrap(), [["viewer", "doc.pdf"]]);
+    }
+
+    #[test]
+    fn to_command_keeps_the_terminal_and_the_working_dir() {
+        let source = "[Desktop Entry]\nName=Neovim\nExec=nvim %F\nTerminal=true\nPath=/srv\n";
+        let nvim = DesktopEntry::parse(Path::new("/apps/nvim.desktop"), source);
+        let command = nvim.to_command().unwrap();
+        assert_eq!(
+            command.to_string(),
+            r#"{ cmd = "nvim %F", terminal = true, cwd = "/srv" }"#
+        );
+
+        let command = entry("viewer --name=%c %f").to_command().unwrap();
+        assert_eq!(
+            command,
+            Command::Shell("viewer --name=Viewer %f".to_string())
+        );
+    }
+
+    #[test]
+    fn takes_many_needs_a_field_code_for_many_or_none_for_one() {
+        let args = |exec: &str| split_exec(exec).unwrap();
+        assert!(takes_many(&args("viewer %F")));
+        assert!(takes_many(&args("viewer")));
+        assert!(!takes_many(&args("viewer %u")));
+    }
+}
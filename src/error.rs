@@ -3,3 +3,7 @@ use colored::Colorize;
 pub fn print_error(msg: impl Into<String>) {
     eprintln!("{}: {}", "Error".bold().red(), msg.into())
 }
+
+pub fn print_warning(msg: impl Into<String>) {
+    eprintln!("{}: {}", "Warning".bold().yellow(), msg.into())
+}
@@ -1,7 +1,13 @@
+mod chooser;
+mod command;
 mod config;
+mod desktop;
 mod error;
 mod mime_helpers;
+mod mimeapps;
 mod opt;
+mod target;
+mod xdg;
 
 use std::process;
 
@@ -12,7 +18,6 @@ use structopt::StructOpt;
 
 use error::print_error;
 use opt::Opt;
-use opt::Runable;
 
 /// Start the logger depending on the verbosity flag
 fn start_logger(opt: &Opt) {
@@ -1,26 +1,93 @@
+mod mime_db;
+
 use anyhow::{bail, Context, Result};
 use mime::Mime;
+use serde_derive::Deserialize;
+use std::fmt;
+use std::fs::{self, Metadata};
+use std::io::Read;
+use std::os::unix::fs::FileTypeExt;
 use std::path::Path;
 
-pub fn mime_equal(m1: &Mime, m2: &Mime) -> bool {
-    let m1_type = m1.type_().as_str();
-    let m1_subtype = m1.subtype().as_str();
-    let m2_type = m2.type_().as_str();
-    let m2_subtype = m2.subtype().as_str();
+use mime_db::MimeDb;
+
+/// How well the mime of a rule matches the mime of a file. The variants are ordered from the best
+/// match to the worst, so the smallest `Specificity` wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Specificity {
+    /// The rule is exactly the same as the mime, including the parameters
+    Exact,
+    /// The rule has the same essence as the mime and the parameters of the rule are all in the mime
+    Parameters,
+    /// The rule and the mime are aliases of each other, like application/x-pdf and application/pdf
+    Alias,
+    /// The rule is an ancestor of the mime, like text/plain for text/x-rust. The number is how many
+    /// levels up the rule is.
+    Subclass(usize),
+    /// The rule is a star mime for the type of the mime, like text/*
+    TypeWildcard,
+    /// The rule is */*
+    AnyWildcard,
+}
 
-    if m1 == m2 {
-        return true;
+impl fmt::Display for Specificity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Specificity::Exact => write!(f, "exact match"),
+            Specificity::Parameters => write!(f, "same mime with matching parameters"),
+            Specificity::Alias => write!(f, "alias"),
+            Specificity::Subclass(1) => write!(f, "parent"),
+            Specificity::Subclass(depth) => write!(f, "ancestor {} levels up", depth),
+            Specificity::TypeWildcard => write!(f, "star mime for the type"),
+            Specificity::AnyWildcard => write!(f, "matches any mime"),
+        }
     }
+}
 
-    if m1_type == m2_type {
-        if m2_subtype == "*" {
-            return true;
-        } else if m1_subtype == "*" {
-            return true;
+/// Finds how well `rule` matches `mime`. Returns None if it does not match at all.
+pub fn specificity(mime: &Mime, rule: &Mime) -> Option<Specificity> {
+    if mime.essence_str() == rule.essence_str() {
+        if same_mime(mime, rule) {
+            return Some(Specificity::Exact);
         }
+        let params_match = rule
+            .params()
+            .all(|(name, value)| mime.get_param(name) == Some(value));
+        return if params_match {
+            Some(Specificity::Parameters)
+        } else {
+            None
+        };
+    }
+
+    let db = MimeDb::get();
+    if db.canonical(mime.essence_str()) == db.canonical(rule.essence_str()) {
+        return Some(Specificity::Alias);
+    }
+    if let Some(depth) = db.ancestor_depth(mime.essence_str(), rule.essence_str()) {
+        return Some(Specificity::Subclass(depth));
+    }
+
+    match (rule.type_().as_str(), rule.subtype().as_str()) {
+        ("*", "*") => Some(Specificity::AnyWildcard),
+        (type_, "*") if type_ == mime.type_().as_str() => Some(Specificity::TypeWildcard),
+        _ => None,
     }
+}
+
+/// Whether two mimes are the same, including the values of their parameters in any order. The
+/// `==` of `Mime` does not compare the values of the parameters.
+pub fn same_mime(mime: &Mime, other: &Mime) -> bool {
+    mime.essence_str() == other.essence_str()
+        && mime.params().count() == other.params().count()
+        && mime
+            .params()
+            .all(|(name, value)| other.get_param(name) == Some(value))
+}
 
-    false
+/// Every mime that is known from the shared-mime-info database, sorted
+pub fn known_mimes() -> Vec<&'static str> {
+    MimeDb::get().types().collect()
 }
 
 fn tree_magic_mime(path: impl AsRef<Path>) -> Result<Mime> {
@@ -28,7 +95,7 @@ fn tree_magic_mime(path: impl AsRef<Path>) -> Result<Mime> {
     if !path.exists() {
         bail!("The path {} does not exist", path.display());
     }
-    let mime_string = tree_magic::from_filepath(path.as_ref());
+    let mime_string = tree_magic::from_filepath(path);
 
     mime_string.parse::<Mime>().context(format!(
         "Failed to parse string {} returned by tree_magic into a mime type",
@@ -36,8 +103,505 @@ fn tree_magic_mime(path: impl AsRef<Path>) -> Result<Mime> {
     ))
 }
 
-/// Determines the mime type from the given path. First uses the extension and then uses tree_magic
-/// if using the extension failed.
-pub fn determine_mime(path: impl AsRef<Path>) -> Result<Mime> {
-    Ok(mime_guess::from_path(&path).first_or(tree_magic_mime(&path)?))
+/// A way to find the mime type of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Detector {
+    /// The extension of the path
+    Extension,
+    /// The contents of the file using tree_magic
+    Magic,
+    /// The interpreter in the `#!` line of a script
+    Shebang,
+    /// The `user.mime_type` extended attribute of the file
+    Xattr,
+}
+
+impl fmt::Display for Detector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Detector::Extension => write!(f, "extension"),
+            Detector::Magic => write!(f, "magic"),
+            Detector::Shebang => write!(f, "shebang"),
+            Detector::Xattr => write!(f, "xattr"),
+        }
+    }
+}
+
+/// How the mime types of files are found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Detection {
+    /// The detectors to try in order, the first one that finds a mime is used
+    pub order: Vec<Detector>,
+    /// Whether to warn when the extension of a file does not match its contents
+    pub warn_mismatch: bool,
+}
+
+impl Default for Detection {
+    fn default() -> Self {
+        Detection {
+            order: vec![Detector::Extension, Detector::Magic],
+            warn_mismatch: false,
+        }
+    }
+}
+
+/// How the mime type of a path was determined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MimeSource {
+    /// From the extension of the path
+    Extension,
+    /// From the contents of the file using tree_magic
+    Magic,
+    /// From the interpreter in the `#!` line
+    Shebang,
+    /// From the `user.mime_type` extended attribute
+    Xattr,
+    /// From the type of the file, like a directory or an empty file
+    FileType,
+    /// From the scheme of a URI
+    Scheme,
+    /// From the Content-Type that the probe command found for a URL
+    Probe,
+}
+
+impl fmt::Display for MimeSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MimeSource::Extension => write!(f, "extension"),
+            MimeSource::Magic => write!(f, "tree_magic"),
+            MimeSource::Shebang => write!(f, "the shebang"),
+            MimeSource::Xattr => write!(f, "the user.mime_type attribute"),
+            MimeSource::FileType => write!(f, "the file type"),
+            MimeSource::Scheme => write!(f, "the URI scheme"),
+            MimeSource::Probe => write!(f, "probing the URL"),
+        }
+    }
+}
+
+/// Determines the mime type of the path and how it was determined. Directories, special files
+/// and broken symlinks get an `inode/*` mime from their type. Otherwise the detectors are tried in
+/// order and only run when the ones before them found nothing, so the contents are not read if
+/// the extension is known. Empty files are `application/x-zerosize` to magic.
+pub fn determine_mime_with_source(
+    path: impl AsRef<Path>,
+    detection: &Detection,
+) -> Result<(Mime, MimeSource)> {
+    let path = path.as_ref();
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        // the link exists but what it points to does not
+        Err(_) if path.is_symlink() => return Ok((inode_mime("symlink"), MimeSource::FileType)),
+        Err(_) => bail!("The path {} does not exist", path.display()),
+    };
+    if let Some(mime) = file_type_mime(&metadata) {
+        return Ok((mime, MimeSource::FileType));
+    }
+
+    // tree_magic gives application/octet-stream for anything it does not recognize, so the
+    // detectors after it get a chance to do better
+    let mut unknown = None;
+    for detector in &detection.order {
+        let found = match detector {
+            Detector::Extension => mime_guess::from_path(path)
+                .first()
+                .map(|mime| (mime, MimeSource::Extension)),
+            Detector::Magic if metadata.len() == 0 => {
+                Some((mime_of("application/x-zerosize"), MimeSource::FileType))
+            }
+            Detector::Magic => Some((tree_magic_mime(path)?, MimeSource::Magic)),
+            Detector::Shebang => shebang_mime(path)?.map(|mime| (mime, MimeSource::Shebang)),
+            Detector::Xattr => xattr_mime(path).map(|mime| (mime, MimeSource::Xattr)),
+        };
+        match found {
+            Some((mime, source)) if mime == mime::APPLICATION_OCTET_STREAM => {
+                unknown = unknown.or(Some((mime, source)))
+            }
+            Some(found) => return Ok(found),
+            None => {}
+        }
+    }
+
+    match unknown {
+        Some(unknown) => Ok(unknown),
+        None => {
+            let order: Vec<String> = detection.order.iter().map(Detector::to_string).collect();
+            bail!(
+                "Failed to find the mime of {} from its {}",
+                path.display(),
+                order.join(", ")
+            )
+        }
+    }
+}
+
+/// The mime of the extension and the mime of the contents of a file when they disagree, like a
+/// `.txt` file that is really a PNG. None if they agree or either of them is not known. A mime
+/// agrees with its aliases and ancestors, so a `.rs` file that tree_magic sees as text/plain is
+/// fine.
+pub fn content_mismatch(path: impl AsRef<Path>) -> Option<(Mime, Mime)> {
+    let path = path.as_ref();
+    let extension = mime_guess::from_path(path).first()?;
+    let metadata = fs::metadata(path).ok()?;
+    if !metadata.is_file() || metadata.len() == 0 {
+        return None;
+    }
+    let content = tree_magic_mime(path).ok()?;
+    if content == mime::APPLICATION_OCTET_STREAM {
+        return None;
+    }
+
+    let related = |mime: &Mime, other: &Mime| {
+        specificity(mime, other).is_some_and(|specificity| specificity < Specificity::TypeWildcard)
+    };
+    if related(&extension, &content) || related(&content, &extension) {
+        None
+    } else {
+        Some((extension, content))
+    }
+}
+
+/// The mime of a script from the interpreter in its `#!` line, like application/x-shellscript
+/// for `#!/bin/sh` or text/x-python for `#!/usr/bin/env python3`. None if the file has no `#!`
+/// line or the interpreter is not known.
+fn shebang_mime(path: &Path) -> Result<Option<Mime>> {
+    let mut start = [0; 256];
+    let mut file = fs::File::open(path).context(format!("Failed to open {}", path.display()))?;
+    let read = file
+        .read(&mut start)
+        .context(format!("Failed to read {}", path.display()))?;
+    let line = match start[..read].strip_prefix(b"#!") {
+        Some(rest) => rest.split(|byte| *byte == b'\n').next().unwrap_or_default(),
+        None => return Ok(None),
+    };
+    let line = String::from_utf8_lossy(line);
+
+    let mut words = line.split_whitespace();
+    let mut program = words.next().unwrap_or_default();
+    if program.rsplit('/').next() == Some("env") {
+        // skip the options and variables of env, like `env -S` or `env LANG=C`
+        program = words
+            .find(|word| !word.starts_with('-') && !word.contains('='))
+            .unwrap_or_default();
+    }
+    // python3.11 is python
+    let name = program
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+
+    let mime = match name {
+        "sh" | "bash" | "dash" | "zsh" | "ksh" | "mksh" | "ash" => "application/x-shellscript",
+        "python" => "text/x-python",
+        "perl" => "application/x-perl",
+        "ruby" => "application/x-ruby",
+        "node" | "nodejs" => "application/javascript",
+        "lua" => "text/x-lua",
+        "php" => "application/x-php",
+        "awk" | "gawk" => "application/x-awk",
+        "tclsh" | "wish" => "text/x-tcl",
+        _ => return Ok(None),
+    };
+    Ok(Some(mime_of(mime)))
+}
+
+/// The mime in the `user.mime_type` extended attribute of the file, from the shared-mime-info
+/// spec. None if the file does not have it or it is not a valid mime.
+#[cfg(target_os = "linux")]
+fn xattr_mime(path: &Path) -> Option<Mime> {
+    use std::convert::TryFrom;
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    const NAME: &[u8] = b"user.mime_type\0";
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut value = [0u8; 256];
+    // the path and the name end with NUL and getxattr writes at most the size of the buffer
+    let len = unsafe {
+        libc::getxattr(
+            path.as_ptr(),
+            NAME.as_ptr().cast(),
+            value.as_mut_ptr().cast(),
+            value.len(),
+        )
+    };
+    let len = usize::try_from(len).ok()?;
+    let value = std::str::from_utf8(&value[..len]).ok()?;
+    value.trim_end_matches('\0').trim().parse().ok()
+}
+
+/// The arguments of getxattr differ between platforms, so extended attributes are only read on
+/// Linux
+#[cfg(not(target_os = "linux"))]
+fn xattr_mime(_path: &Path) -> Option<Mime> {
+    None
+}
+
+/// The `inode/*` mime of a directory or a special file, None for regular files. Symlinks are
+/// followed before this, so only broken ones are `inode/symlink`.
+fn file_type_mime(metadata: &Metadata) -> Option<Mime> {
+    let file_type = metadata.file_type();
+    let name = if file_type.is_dir() {
+        "directory"
+    } else if file_type.is_socket() {
+        "socket"
+    } else if file_type.is_fifo() {
+        "fifo"
+    } else if file_type.is_block_device() {
+        "blockdevice"
+    } else if file_type.is_char_device() {
+        "chardevice"
+    } else {
+        return None;
+    };
+    Some(inode_mime(name))
+}
+
+/// The `inode/*` mime with the subtype
+fn inode_mime(subtype: &str) -> Mime {
+    mime_of(&format!("inode/{}", subtype))
+}
+
+/// Parses a mime that is known to be valid
+fn mime_of(mime: &str) -> Mime {
+    mime.parse()
+        .unwrap_or_else(|_| panic!("BUG: {} is a valid mime", mime))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rank(mime: &str, rule: &str) -> Option<Specificity> {
+        specificity(&mime_of(mime), &mime_of(rule))
+    }
+
+    #[test]
+    fn specificity_of_each_kind_of_match() {
+        let mime = "text/plain; charset=utf-8";
+        assert_eq!(
+            rank(mime, "text/plain; charset=utf-8"),
+            Some(Specificity::Exact)
+        );
+        assert_eq!(rank(mime, "text/plain"), Some(Specificity::Parameters));
+        assert_eq!(rank(mime, "text/plain; charset=latin1"), None);
+        assert_eq!(
+            rank("application/x-pdf", "application/pdf"),
+            Some(Specificity::Alias)
+        );
+        assert_eq!(
+            rank("application/pdf", "application/x-pdf"),
+            Some(Specificity::Alias)
+        );
+        assert_eq!(
+            rank("text/x-csrc", "text/plain"),
+            Some(Specificity::Subclass(1))
+        );
+        assert_eq!(
+            rank("text/x-chdr", "text/plain"),
+            Some(Specificity::Subclass(2))
+        );
+        assert_eq!(
+            rank("text/x-rust", "text/*"),
+            Some(Specificity::TypeWildcard)
+        );
+        assert_eq!(rank("image/png", "*/*"), Some(Specificity::AnyWildcard));
+        assert_eq!(
+            rank("text/plain; a=1; b=2", "text/plain; b=2; a=1"),
+            Some(Specificity::Exact)
+        );
+        assert_eq!(rank("text/plain; a=1", "text/plain; a=2"), None);
+        assert_eq!(rank("image/png", "text/*"), None);
+        assert_eq!(rank("text/plain", "text/x-rust"), None);
+    }
+
+    #[test]
+    fn specificity_ranks_from_the_best_match_to_the_worst() {
+        let mut ranks = vec![
+            Specificity::AnyWildcard,
+            Specificity::Subclass(2),
+            Specificity::Alias,
+            Specificity::TypeWildcard,
+            Specificity::Exact,
+            Specificity::Subclass(1),
+            Specificity::Parameters,
+        ];
+        ranks.sort();
+        assert_eq!(
+            ranks,
+            [
+                Specificity::Exact,
+                Specificity::Parameters,
+                Specificity::Alias,
+                Specificity::Subclass(1),
+                Specificity::Subclass(2),
+                Specificity::TypeWildcard,
+                Specificity::AnyWildcard,
+            ]
+        );
+    }
+
+    #[test]
+    fn best_rule_for_a_mime_is_the_most_specific() {
+        let rules = ["*/*", "text/*", "text/plain", "text/x-csrc"];
+        let best = rules
+            .iter()
+            .filter_map(|rule| rank("text/x-chdr", rule).map(|rank| (rank, *rule)))
+            .min();
+        assert_eq!(best, Some((Specificity::Subclass(1), "text/x-csrc")));
+    }
+
+    /// A new empty directory for the test, removed by `remove_dir`
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("opener-mime-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn detect(path: &Path) -> (String, MimeSource) {
+        let (mime, source) = determine_mime_with_source(path, &Detection::default()).unwrap();
+        (mime.to_string(), source)
+    }
+
+    #[test]
+    fn special_files_get_an_inode_mime_from_their_type() {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::symlink;
+        use std::os::unix::net::UnixListener;
+
+        let dir = temp_dir("inode");
+        // directories with an extension are still directories
+        let directory = dir.join("photos.png");
+        fs::create_dir(&directory).unwrap();
+        let link_to_directory = dir.join("link");
+        symlink(&directory, &link_to_directory).unwrap();
+        let broken = dir.join("broken.txt");
+        symlink(dir.join("missing"), &broken).unwrap();
+        let fifo = dir.join("fifo");
+        let fifo_c = std::ffi::CString::new(fifo.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo_c.as_ptr(), 0o600) }, 0);
+        let socket = dir.join("socket");
+        let _listener = UnixListener::bind(&socket).unwrap();
+
+        let inode = |subtype: &str| (format!("inode/{}", subtype), MimeSource::FileType);
+        assert_eq!(detect(&directory), inode("directory"));
+        assert_eq!(detect(&link_to_directory), inode("directory"));
+        assert_eq!(detect(&broken), inode("symlink"));
+        assert_eq!(detect(&fifo), inode("fifo"));
+        assert_eq!(detect(&socket), inode("socket"));
+        assert_eq!(detect(Path::new("/dev/null")), inode("chardevice"));
+        assert!(determine_mime_with_source(dir.join("missing"), &Detection::default()).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn shebang(dir: &Path, line: &str) -> Option<String> {
+        let path = dir.join("script");
+        fs::write(&path, line).unwrap();
+        shebang_mime(&path).unwrap().map(|mime| mime.to_string())
+    }
+
+    #[test]
+    fn shebang_mime_finds_the_interpreter() {
+        let dir = temp_dir("shebang");
+        let found = |line| shebang(&dir, line);
+        assert_eq!(
+            found("#!/bin/sh\necho"),
+            Some("application/x-shellscript".to_string())
+        );
+        assert_eq!(
+            found("#! /usr/bin/bash -e"),
+            Some("application/x-shellscript".to_string())
+        );
+        assert_eq!(
+            found("#!/usr/bin/python3.11"),
+            Some("text/x-python".to_string())
+        );
+        assert_eq!(
+            found("#!/usr/bin/env python3"),
+            Some("text/x-python".to_string())
+        );
+        assert_eq!(
+            found("#!/usr/bin/env -S LANG=C node --harmony"),
+            Some("application/javascript".to_string())
+        );
+        assert_eq!(found("#!/usr/bin/env"), None);
+        assert_eq!(found("#!/usr/bin/unknown"), None);
+        assert_eq!(found("echo #!/bin/sh"), None);
+        assert_eq!(found(""), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn detectors_are_tried_in_order() {
+        let dir = temp_dir("order");
+        // a shell script named like a text file
+        let script = dir.join("run.txt");
+        fs::write(&script, "#!/bin/sh\necho hello\n").unwrap();
+        let detect_with = |order: Vec<Detector>| {
+            let detection = Detection {
+                order,
+                warn_mismatch: false,
+            };
+            let (mime, source) = determine_mime_with_source(&script, &detection).unwrap();
+            (mime.essence_str().to_string(), source)
+        };
+
+        assert_eq!(
+            detect_with(vec![Detector::Extension, Detector::Shebang]),
+            ("text/plain".to_string(), MimeSource::Extension)
+        );
+        assert_eq!(
+            detect_with(vec![Detector::Shebang, Detector::Extension]),
+            ("application/x-shellscript".to_string(), MimeSource::Shebang)
+        );
+        // a detector that finds nothing falls through to the next one
+        let unknown = dir.join("no-extension");
+        fs::write(&unknown, "#!/bin/sh\n").unwrap();
+        let detection = Detection {
+            order: vec![Detector::Extension, Detector::Xattr, Detector::Shebang],
+            warn_mismatch: false,
+        };
+        let (mime, source) = determine_mime_with_source(&unknown, &detection).unwrap();
+        assert_eq!(
+            (mime.essence_str(), source),
+            ("application/x-shellscript", MimeSource::Shebang)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_magic_calls_unknown_files_octet_stream() {
+        let dir = temp_dir("unknown");
+        let binary = dir.join("data");
+        fs::write(&binary, [0u8, 159, 146, 150, 1, 2, 3]).unwrap();
+        let detection = |order| Detection {
+            order,
+            warn_mismatch: false,
+        };
+
+        let (mime, source) =
+            determine_mime_with_source(&binary, &detection(vec![Detector::Magic])).unwrap();
+        assert_eq!(
+            (mime, source),
+            (mime::APPLICATION_OCTET_STREAM, MimeSource::Magic)
+        );
+        let error =
+            determine_mime_with_source(&binary, &detection(vec![Detector::Extension])).unwrap_err();
+        assert!(error.to_string().contains("from its extension"));
+
+        // empty files are application/x-zerosize without reading them
+        let empty = dir.join("empty");
+        fs::write(&empty, "").unwrap();
+        let (mime, source) =
+            determine_mime_with_source(&empty, &detection(vec![Detector::Magic])).unwrap();
+        assert_eq!(
+            (mime.to_string(), source),
+            ("application/x-zerosize".to_string(), MimeSource::FileType)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
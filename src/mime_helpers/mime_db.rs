@@ -0,0 +1,245 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fs;
+use std::sync::OnceLock;
+
+use log::*;
+
+use crate::xdg;
+
+/// The aliases that are bundled with opener, in the format of shared-mime-info's `aliases` file
+const BUNDLED_ALIASES: &str = include_str!("aliases");
+
+/// The subclasses that are bundled with opener, in the format of shared-mime-info's `subclasses`
+/// file
+const BUNDLED_SUBCLASSES: &str = include_str!("subclasses");
+
+/// Every text mime is implicitly a subclass of this one
+const TEXT_PLAIN: &str = "text/plain";
+
+/// Knows which mime types are aliases of each other and which mime types are subclasses of others.
+/// For example `application/x-pdf` is an alias of `application/pdf` and `text/x-rust` is a subclass
+/// of `text/plain`.
+#[derive(Debug, Default)]
+pub struct MimeDb {
+    /// alias -> canonical mime
+    aliases: HashMap<String, String>,
+    /// mime -> the mimes it is a direct subclass of
+    parents: HashMap<String, Vec<String>>,
+    /// Every mime that is known, from the `types` file
+    types: BTreeSet<String>,
+}
+
+impl MimeDb {
+    /// Gets the database, loading it the first time this is called
+    pub fn get() -> &'static MimeDb {
+        static DB: OnceLock<MimeDb> = OnceLock::new();
+        DB.get_or_init(|| MimeDb::load().unwrap_or_else(MimeDb::bundled))
+    }
+
+    /// Loads the shared-mime-info database from the `mime` directory in each of the xdg data
+    /// directories. The directories that come first take precedence. Returns None if there is no
+    /// database on the system.
+    fn load() -> Option<MimeDb> {
+        let mut db = MimeDb::default();
+        let mut found = false;
+        for dir in xdg::data_dirs() {
+            let dir = dir.join("mime");
+            if let Ok(contents) = fs::read_to_string(dir.join("aliases")) {
+                debug!("Loading mime aliases from {}", dir.display());
+                db.add_aliases(&contents);
+                found = true;
+            }
+            if let Ok(contents) = fs::read_to_string(dir.join("subclasses")) {
+                debug!("Loading mime subclasses from {}", dir.display());
+                db.add_subclasses(&contents);
+                found = true;
+            }
+            if let Ok(contents) = fs::read_to_string(dir.join("types")) {
+                debug!("Loading mime types from {}", dir.display());
+                let types = contents.lines().map(str::trim).filter(|l| !l.is_empty());
+                db.types.extend(types.map(str::to_string));
+                found = true;
+            }
+        }
+
+        if found {
+            Some(db)
+        } else {
+            debug!("No shared-mime-info database was found, using the bundled one");
+            None
+        }
+    }
+
+    /// The database that is bundled with opener
+    fn bundled() -> MimeDb {
+        let mut db = MimeDb::default();
+        db.add_aliases(BUNDLED_ALIASES);
+        db.add_subclasses(BUNDLED_SUBCLASSES);
+        // there is no bundled types file, so the mimes in the other files are all that is known
+        let known: Vec<String> = db
+            .aliases
+            .values()
+            .chain(db.parents.keys())
+            .chain(db.parents.values().flatten())
+            .cloned()
+            .collect();
+        db.types.extend(known);
+        db
+    }
+
+    /// Adds the aliases from the contents of an `aliases` file. Each line is `alias canonical`.
+    fn add_aliases(&mut self, contents: &str) {
+        for (alias, canonical) in pairs(contents) {
+            self.aliases
+                .entry(alias.to_string())
+                .or_insert_with(|| canonical.to_string());
+        }
+    }
+
+    /// Adds the subclasses from the contents of a `subclasses` file. Each line is `mime parent`.
+    fn add_subclasses(&mut self, contents: &str) {
+        for (mime, parent) in pairs(contents) {
+            let parents = self.parents.entry(mime.to_string()).or_default();
+            if !parents.iter().any(|p| p == parent) {
+                parents.push(parent.to_string());
+            }
+        }
+    }
+
+    /// Every mime that is known, sorted
+    pub fn types(&self) -> impl Iterator<Item = &str> {
+        self.types.iter().map(String::as_str)
+    }
+
+    /// Resolves an alias to the canonical mime. Mimes that are not aliases are returned as is.
+    pub fn canonical<'a>(&'a self, mime: &'a str) -> &'a str {
+        self.aliases.get(mime).map(String::as_str).unwrap_or(mime)
+    }
+
+    /// How many levels up `ancestor` is from `mime`, following the subclass relationships. The
+    /// closest ancestor wins if it can be reached in more than one way. Returns None if `ancestor`
+    /// is not an ancestor of `mime`.
+    pub fn ancestor_depth(&self, mime: &str, ancestor: &str) -> Option<usize> {
+        let mime = self.canonical(mime);
+        let ancestor = self.canonical(ancestor);
+
+        // breadth first so that the closest ancestor is found first
+        let mut queue: VecDeque<(&str, usize)> = VecDeque::new();
+        let mut seen: Vec<&str> = vec![mime];
+        queue.push_back((mime, 0));
+        while let Some((current, depth)) = queue.pop_front() {
+            for parent in self.parents_of(current) {
+                let parent = self.canonical(parent);
+                if parent == ancestor {
+                    return Some(depth + 1);
+                }
+                if !seen.contains(&parent) {
+                    seen.push(parent);
+                    queue.push_back((parent, depth + 1));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// The direct parents of a mime. Text mimes without any parents are a subclass of text/plain.
+    fn parents_of<'a>(&'a self, mime: &'a str) -> Vec<&'a str> {
+        let mut parents: Vec<&str> = self
+            .parents
+            .get(mime)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        if parents.is_empty() && mime.starts_with("text/") && mime != TEXT_PLAIN {
+            parents.push(TEXT_PLAIN);
+        }
+        parents
+    }
+}
+
+/// Parses the lines of a shared-mime-info file made of pairs of mimes separated by whitespace
+fn pairs(contents: &str) -> impl Iterator<Item = (&str, &str)> {
+    contents.lines().filter_map(|line| {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some(first), Some(second)) if !first.starts_with('#') => Some((first, second)),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_skip_comments_and_incomplete_lines() {
+        let contents =
+            "# a comment\napplication/x-pdf application/pdf\n\nlonely/mime\n  text/a   text/b  \n";
+        let found: Vec<(&str, &str)> = pairs(contents).collect();
+        assert_eq!(
+            found,
+            [
+                ("application/x-pdf", "application/pdf"),
+                ("text/a", "text/b")
+            ]
+        );
+    }
+
+    #[test]
+    fn first_alias_wins_and_parents_are_not_repeated() {
+        let mut db = MimeDb::default();
+        db.add_aliases("application/x-a application/a\n");
+        db.add_aliases("application/x-a application/b\n");
+        db.add_subclasses("text/x-a text/x-b\ntext/x-a text/x-b\ntext/x-a text/x-c\n");
+        db.add_subclasses("text/x-a text/x-b\n");
+
+        assert_eq!(db.canonical("application/x-a"), "application/a");
+        assert_eq!(db.canonical("application/a"), "application/a");
+        assert_eq!(db.parents["text/x-a"], ["text/x-b", "text/x-c"]);
+    }
+
+    #[test]
+    fn ancestor_depth_finds_the_closest_ancestor() {
+        let mut db = MimeDb::default();
+        db.add_aliases("application/x-a application/a\n");
+        db.add_subclasses(
+            "application/a application/b\napplication/b application/c\napplication/a application/c\n",
+        );
+
+        assert_eq!(
+            db.ancestor_depth("application/x-a", "application/b"),
+            Some(1)
+        );
+        assert_eq!(db.ancestor_depth("application/a", "application/c"), Some(1));
+        assert_eq!(db.ancestor_depth("application/b", "application/a"), None);
+    }
+
+    #[test]
+    fn text_mimes_without_parents_are_text_plain() {
+        let db = MimeDb::default();
+        assert_eq!(db.ancestor_depth("text/x-unknown", TEXT_PLAIN), Some(1));
+        assert_eq!(db.ancestor_depth(TEXT_PLAIN, TEXT_PLAIN), None);
+        assert_eq!(db.ancestor_depth("image/x-unknown", TEXT_PLAIN), None);
+    }
+
+    #[test]
+    fn bundled_database_knows_the_mimes_in_its_files() {
+        let db = MimeDb::bundled();
+        assert_eq!(db.canonical("application/x-pdf"), "application/pdf");
+        assert_eq!(db.ancestor_depth("text/x-chdr", TEXT_PLAIN), Some(2));
+        assert_eq!(
+            db.ancestor_depth("application/x-sh", "application/x-executable"),
+            Some(1)
+        );
+
+        let types: Vec<&str> = db.types().collect();
+        assert!(types.contains(&"application/pdf"));
+        assert!(types.contains(&"text/x-chdr"));
+        assert!(types.contains(&"text/plain"));
+        // the types are sorted and unique
+        assert!(types.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
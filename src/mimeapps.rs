@@ -0,0 +1,156 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use directories::BaseDirs;
+
+use crate::desktop::parse_key_file;
+
+/// The group with the default application of each mime
+const DEFAULT_APPLICATIONS: &str = "Default Applications";
+
+/// The group with more applications that can open each mime
+const ADDED_ASSOCIATIONS: &str = "Added Associations";
+
+/// The group with applications that should not be used for a mime
+const REMOVED_ASSOCIATIONS: &str = "Removed Associations";
+
+/// The start of the name of every desktop file that opener export generates
+pub const EXPORTED_PREFIX: &str = "opener-";
+
+/// The associations between mimes and desktop file ids in a mimeapps.list file
+#[derive(Debug, Default)]
+pub struct MimeApps {
+    /// Each mime and the desktop file ids that can open it, the default one first
+    pub associations: Vec<(String, Vec<String>)>,
+}
+
+impl MimeApps {
+    /// Loads the mimeapps.list file at the path
+    pub fn load(path: &Path) -> Result<MimeApps> {
+        let source = fs::read_to_string(path)
+            .context(format!("Failed to read the mimeapps.list file {}", path.display()))?;
+        Ok(MimeApps::parse(&source))
+    }
+
+    /// Parses a mimeapps.list file. The default applications go before the added associations and
+    /// removed associations are left out.
+    pub fn parse(source: &str) -> MimeApps {
+        let groups = parse_key_file(source);
+        let group = |name: &str| {
+            groups
+                .iter()
+                .filter(|(group, _)| group == name)
+                .flat_map(|(_, keys)| keys.iter())
+                .map(|(mime, ids)| (mime.as_str(), split_list(ids)))
+                .collect::<Vec<_>>()
+        };
+        let removed = group(REMOVED_ASSOCIATIONS);
+
+        let mut mimeapps = MimeApps::default();
+        for (mime, ids) in group(DEFAULT_APPLICATIONS)
+            .into_iter()
+            .chain(group(ADDED_ASSOCIATIONS))
+        {
+            let ids = ids.into_iter().filter(|id| {
+                !removed
+                    .iter()
+                    .any(|(removed_mime, removed_ids)| *removed_mime == mime && removed_ids.contains(id))
+            });
+            match mimeapps.associations.iter_mut().find(|(m, _)| m == mime) {
+                Some((_, existing)) => {
+                    for id in ids {
+                        if !existing.contains(&id) {
+                            existing.push(id);
+                        }
+                    }
+                }
+                None => mimeapps.associations.push((mime.to_string(), ids.collect())),
+            }
+        }
+        mimeapps
+    }
+}
+
+/// Sets the default applications of the mimes in the contents of a mimeapps.list file, keeping
+/// the rest of the file as it is. Default applications of other mimes are removed if every
+/// application in them `is_stale`, so applications that no longer exist can be cleaned up.
+pub fn set_defaults(
+    source: &str,
+    defaults: &[(String, Vec<String>)],
+    is_stale: impl Fn(&str) -> bool,
+) -> String {
+    let new_lines: Vec<String> = defaults
+        .iter()
+        .map(|(mime, ids)| format!("{}={};", mime, ids.join(";")))
+        .collect();
+
+    let mut lines: Vec<String> = Vec::new();
+    let mut in_defaults = false;
+    let mut inserted = false;
+    for line in source.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            in_defaults = trimmed == format!("[{}]", DEFAULT_APPLICATIONS);
+            lines.push(line.to_string());
+            if in_defaults && !inserted {
+                lines.extend(new_lines.iter().cloned());
+                inserted = true;
+            }
+            continue;
+        }
+
+        if in_defaults {
+            if let Some((mime, ids)) = trimmed.split_once('=') {
+                let ids = split_list(ids);
+                let replaced = defaults.iter().any(|(m, _)| m == mime.trim());
+                if replaced || (!ids.is_empty() && ids.iter().all(|id| is_stale(id))) {
+                    continue;
+                }
+            }
+        }
+        lines.push(line.to_string());
+    }
+
+    if !inserted {
+        if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+            lines.push(String::new());
+        }
+        lines.push(format!("[{}]", DEFAULT_APPLICATIONS));
+        lines.extend(new_lines);
+    }
+
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    contents
+}
+
+/// Splits a semicolon separated list like `a.desktop;b.desktop;`
+fn split_list(list: &str) -> Vec<String> {
+    list.split(';')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The mimeapps.list file of the user, `~/.config/mimeapps.list` on linux
+pub fn user_path() -> Result<PathBuf> {
+    let base = BaseDirs::new().ok_or_else(|| anyhow!("Failed to find the config directory"))?;
+    Ok(base.config_dir().join("mimeapps.list"))
+}
+
+/// Whether the default application of the mime in the mimeapps.list of the user is a desktop file
+/// from opener export. xdg-open would run opener again for it.
+pub fn default_is_exported(mime: &str) -> bool {
+    let mimeapps = match user_path().and_then(|path| MimeApps::load(&path)) {
+        Ok(mimeapps) => mimeapps,
+        Err(_) => return false,
+    };
+    mimeapps
+        .associations
+        .iter()
+        .find(|(other, _)| other == mime)
+        .and_then(|(_, ids)| ids.first())
+        .is_some_and(|id| id.starts_with(EXPORTED_PREFIX))
+}
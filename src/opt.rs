@@ -4,8 +4,10 @@ mod ext_mime_path;
 use std::path::PathBuf;
 
 use structopt::StructOpt;
-use anyhow::Result;
+use anyhow::{bail, Result};
+use log::*;
 
+use crate::config::ConfigPath;
 use subcommand::SubCommand;
 
 /// A tool to unify and make easier xdg-mime and xdg-open. Specify commands for mime types in it's
@@ -15,6 +17,10 @@ pub struct Opt {
     #[structopt(flatten)]
     pub verbose: clap_verbosity_flag::Verbosity,
 
+    /// the config file to use instead of the default one
+    #[structopt(long, global = true, env = "OPENER_CONFIG", parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
     // #[structopt(parse(from_os_str))]
     // pub path: Option<PathBuf>,
 
@@ -24,15 +30,34 @@ pub struct Opt {
 }
 
 pub trait Runable {
-    fn run(self) -> Result<()>;
+    /// Run with the config file at `config_path`
+    fn run(self, config_path: &ConfigPath) -> Result<()>;
 }
 
-impl Runable for Opt {
-    fn run(self) -> Result<()> {
+impl Opt {
+    /// Runs the subcommand using the config file given with --config or OPENER_CONFIG, or the
+    /// default one if neither is given. A config that is given has to exist unless the subcommand
+    /// writes it.
+    pub fn run(self) -> Result<()> {
+        let config_path = match self.config {
+            Some(path) => {
+                // a typo in the path would otherwise quietly use the default config
+                if !path.exists() && !self.subcmd.creates_config() {
+                    bail!(
+                        "The config file {} given with --config or OPENER_CONFIG does not exist",
+                        path.display()
+                    );
+                }
+                ConfigPath::explicit(path)
+            }
+            None => ConfigPath::user()?,
+        };
+        debug!("Using the config file {}", config_path.display());
+
         // match self.subcmd {
         //     Some(subcmd) => subcmd.run(),
         //     None => Ok(())
         // }
-        self.subcmd.run()
+        self.subcmd.run(&config_path)
     }
 }
@@ -4,7 +4,8 @@ use std::convert::TryFrom;
 use anyhow::{anyhow, bail, Result};
 use mime::Mime;
 
-use crate::mime_helpers::determine_mime;
+use crate::config::{ConfigPath, OpenConfig};
+use crate::mime_helpers::determine_mime_with_source;
 
 pub fn parse_addtype(src: &str) -> Result<ExtMimePath, anyhow::Error> {
     ExtMimePath::try_from(src)
@@ -30,7 +31,8 @@ impl TryFrom<&str> for ExtMimePath {
         }
 
         let path = PathBuf::from(value);
-        if path.exists() {
+        // a broken symlink does not exist but still has a mime
+        if path.exists() || path.is_symlink() {
             return Ok(ExtMimePath::Path(path));
         }
 
@@ -38,16 +40,20 @@ impl TryFrom<&str> for ExtMimePath {
     }
 }
 
-impl TryFrom<ExtMimePath> for Mime {
-    type Error = anyhow::Error;
-
-    fn try_from(value: ExtMimePath) -> Result<Self> {
-        match value {
+impl ExtMimePath {
+    /// The mime of the extension, the mime itself, or the mime of the path found with the
+    /// detection of the config that applies to it, the same mime opening the path would use
+    pub fn into_mime(self, config_path: &ConfigPath) -> Result<Mime> {
+        match self {
             ExtMimePath::Extension(ext) => Ok(mime_guess::from_ext(&ext)
                 .first()
                 .ok_or(anyhow!("No mime type found from extension {}", ext))?),
             ExtMimePath::Mime(mime) => Ok(mime),
-            ExtMimePath::Path(path) => determine_mime(path),
+            ExtMimePath::Path(path) => {
+                let config = OpenConfig::load(config_path, path.parent())?;
+                let (mime, _source) = determine_mime_with_source(&path, &config.detection)?;
+                Ok(mime)
+            }
         }
     }
 }
@@ -1,6 +1,13 @@
-mod set;
+mod check;
+mod explain;
+mod export;
+mod import;
+mod init;
+mod list;
 mod open_or_preview;
 mod query;
+mod set;
+mod unset;
 
 use anyhow::Result;
 
@@ -8,13 +15,21 @@ use super::ext_mime_path::{parse_addtype, ExtMimePath};
 
 use super::StructOpt;
 use super::Runable;
-use set::SetOptions;
+use crate::config::ConfigPath;
+use check::CheckOptions;
+use explain::ExplainOptions;
+use export::ExportOptions;
+use import::ImportOptions;
+use init::InitOptions;
+use list::ListOptions;
 use open_or_preview::OpenOptions;
 use query::QueryOptions;
+use set::SetOptions;
+use unset::UnsetOptions;
 
 #[derive(Debug, StructOpt)]
 pub enum SubCommand {
-    /// Open or preview a file with the correct program
+    /// Open or preview files with the correct program
     Open(OpenOptions),
 
     /// Set the correct command for an extension, mime, or path
@@ -22,14 +37,52 @@ pub enum SubCommand {
 
     /// Query for mime types or extensions
     Query(QueryOptions),
+
+    /// Remove the command for an extension, mime, or path
+    #[structopt(visible_alias = "remove")]
+    Unset(UnsetOptions),
+
+    /// List every rule in the config and whether it is valid
+    #[structopt(visible_alias = "show")]
+    List(ListOptions),
+
+    /// Show how the command to open or preview a file is chosen without running it
+    Explain(ExplainOptions),
+
+    /// Check the config for problems and exit with an error if there are any
+    Check(CheckOptions),
+
+    /// Write the default config to the config file
+    Init(InitOptions),
+
+    /// Import rules from the associations of other programs
+    Import(ImportOptions),
+
+    /// Export the rules so that other programs use them too
+    Export(ExportOptions),
+}
+
+impl SubCommand {
+    /// Whether the subcommand can be given a config file that does not exist yet, because it
+    /// writes the config
+    pub fn creates_config(&self) -> bool {
+        matches!(self, SubCommand::Init(_) | SubCommand::Set(_))
+    }
 }
 
 impl Runable for SubCommand {
-    fn run(self) -> Result<()> {
+    fn run(self, config_path: &ConfigPath) -> Result<()> {
         match self {
-            SubCommand::Open(open) => open.run(),
-            SubCommand::Set(add) => add.run(),
-            SubCommand::Query(query) => query.run(),
+            SubCommand::Open(open) => open.run(config_path),
+            SubCommand::Set(add) => add.run(config_path),
+            SubCommand::Query(query) => query.run(config_path),
+            SubCommand::Unset(unset) => unset.run(config_path),
+            SubCommand::List(list) => list.run(config_path),
+            SubCommand::Explain(explain) => explain.run(config_path),
+            SubCommand::Check(check) => check.run(config_path),
+            SubCommand::Init(init) => init.run(config_path),
+            SubCommand::Import(import) => import.run(config_path),
+            SubCommand::Export(export) => export.run(config_path),
         }
     }
 }
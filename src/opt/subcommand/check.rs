@@ -0,0 +1,107 @@
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+use colored::Colorize;
+
+use super::Runable;
+use super::StructOpt;
+use crate::config::{check, ConfigPath, Severity};
+
+/// Options to use for subcommand check
+#[derive(StructOpt, Debug)]
+pub struct CheckOptions {
+    /// exit with an error if there are warnings too
+    #[structopt(long)]
+    strict: bool,
+}
+
+impl Runable for CheckOptions {
+    fn run(self, config_path: &ConfigPath) -> Result<()> {
+        let path = config_path;
+        let source = fs::read_to_string(path)
+            .context(format!("Failed to read the config file {}", path.display()))?;
+
+        let diagnostics = check(&source);
+        let mut errors = 0;
+        let mut warnings = 0;
+        for diagnostic in &diagnostics {
+            let severity = match diagnostic.severity {
+                Severity::Error => {
+                    errors += 1;
+                    "error".bold().red()
+                }
+                Severity::Warning => {
+                    warnings += 1;
+                    "warning".bold().yellow()
+                }
+            };
+            match diagnostic.position {
+                Some((line, column)) => println!(
+                    "{}:{}:{}: {}: {}",
+                    path.display(),
+                    line,
+                    column,
+                    severity,
+                    diagnostic.message
+                ),
+                None => println!("{}: {}: {}", path.display(), severity, diagnostic.message),
+            }
+        }
+
+        if errors > 0 || (self.strict && warnings > 0) {
+            bail!(
+                "{} has {} errors and {} warnings",
+                path.display(),
+                errors,
+                warnings
+            );
+        }
+        println!(
+            "{} is valid with {} warnings",
+            path.display(),
+            warnings
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    /// Checks a config with the source, returns whether opener would exit successfully
+    fn passes(source: &str, strict: bool) -> bool {
+        let path = env::temp_dir().join(format!(
+            "opener-check-{}-{}-{}.toml",
+            process::id(),
+            source.len(),
+            strict
+        ));
+        fs::write(&path, source).unwrap();
+        let result = CheckOptions { strict }.run(&ConfigPath::explicit(path.clone()));
+        fs::remove_file(&path).unwrap();
+        result.is_ok()
+    }
+
+    #[test]
+    fn errors_fail_the_check() {
+        let source = "colour = 'red'\n";
+        assert!(!passes(source, false));
+        assert!(!passes(source, true));
+    }
+
+    #[test]
+    fn warnings_only_fail_the_strict_check() {
+        let source = "chooser = 'no-such-program-for-opener'\n";
+        assert!(passes(source, false));
+        assert!(!passes(source, true));
+    }
+
+    #[test]
+    fn valid_config_passes_the_strict_check() {
+        assert!(passes("chooser = 'prompt'\n", true));
+    }
+}
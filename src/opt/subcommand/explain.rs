@@ -0,0 +1,127 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use mime::Mime;
+
+use super::Runable;
+use super::StructOpt;
+use crate::command::Command;
+use crate::config::{ConfigPath, Narrowable, OpenConfig, PossibleMimes};
+use crate::mime_helpers::{content_mismatch, specificity};
+use crate::target::Target;
+
+/// Options to use for subcommand explain
+#[derive(StructOpt, Debug)]
+pub struct ExplainOptions {
+    #[structopt(parse(from_os_str))]
+    /// the file or URI to explain
+    path: PathBuf,
+
+    /// explain how the file would be previewed instead
+    #[structopt(short, long)]
+    preview: bool,
+}
+
+impl Runable for ExplainOptions {
+    fn run(self, config_path: &ConfigPath) -> Result<()> {
+        let target = Target::parse(&self.path)?;
+        let open_config = OpenConfig::load(config_path, target.dir())?;
+        let mut run_options = open_config.run_options;
+        run_options.preview = self.preview;
+        let (possibilites, possible_regexes, section) = if self.preview {
+            (open_config.preview, open_config.preview_regex, "preview")
+        } else {
+            (open_config.open, open_config.open_regex, "open")
+        };
+
+        let (mime, source) =
+            target.mime_with_source(open_config.url_probe.as_ref(), &open_config.detection)?;
+        let path = target.as_path();
+        // regexes cannot match a path that is not valid UTF-8, so only the mime rules are used
+        let path_str = path.to_str();
+
+        println!("Path: {}", target);
+        println!("Mime: {} (determined by {})", mime, source);
+        if let Target::Path(path) = &target {
+            if let Some((extension, content)) = content_mismatch(path) {
+                println!(
+                    "Warning: the extension is {} but the contents are {}",
+                    extension, content
+                );
+            }
+        }
+
+        // the command chosen by each group, in the order they would be tried
+        let mut commands = Vec::new();
+
+        for (idx, group) in possible_regexes.iter().enumerate() {
+            println!("\n[[{}_regex]] group {} from {}:", section, idx, group.layer());
+            let path_str = match path_str {
+                Some(path_str) => path_str,
+                None => {
+                    println!("  skipped, the path is not valid UTF-8");
+                    continue;
+                }
+            };
+            for (regex, command) in group.rules() {
+                if regex.is_match(path_str) {
+                    println!("  matched   '{}' = {}", regex, command);
+                } else {
+                    println!("  rejected  '{}' = {}: does not match the path", regex, command);
+                }
+            }
+            explain_choice(group, path_str, &mut commands);
+        }
+
+        for (idx, group) in possibilites.iter().enumerate() {
+            println!("\n[[{}]] group {} from {}:", section, idx, group.layer());
+            explain_mimes(group, &mime);
+            explain_choice(group, &mime, &mut commands);
+        }
+
+        println!();
+        if commands.is_empty() {
+            println!("No rule matched, xdg-open would be used");
+            return Ok(());
+        }
+
+        let described: Vec<String> = commands
+            .iter()
+            .map(|command| match command.describe(&[path], &mime, &run_options) {
+                Ok(described) => described,
+                Err(e) => format!("{} (would fail: {})", command, e),
+            })
+            .collect();
+        println!("Commands in the order they would be tried:");
+        for (i, command) in described.iter().enumerate() {
+            println!("  {}. {}", i + 1, command);
+        }
+        println!(
+            "If all of them fail, xdg-open would be used.\n\nopener would run: {}",
+            described[0]
+        );
+
+        Ok(())
+    }
+}
+
+/// Prints why each mime rule of a group matched or was rejected
+fn explain_mimes(group: &PossibleMimes, mime: &Mime) {
+    for (rule, command) in group.rules() {
+        match specificity(mime, rule) {
+            Some(specificity) => println!("  matched   {} = {} ({})", rule, command, specificity),
+            None => println!("  rejected  {} = {}: does not match {}", rule, command, mime),
+        }
+    }
+}
+
+/// Prints the command a group narrows down to and adds it to the commands that would be tried
+fn explain_choice<N: Narrowable>(group: &N, compare: &N::Compare, commands: &mut Vec<Command>) {
+    match group.candidates(compare).into_iter().next() {
+        Some(command) => {
+            println!("  chosen: {}", command);
+            commands.push(command);
+        }
+        None => println!("  nothing matched, going to the next group"),
+    }
+}
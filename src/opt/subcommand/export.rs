@@ -0,0 +1,486 @@
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use directories::BaseDirs;
+use log::*;
+use mime::Mime;
+
+use super::Runable;
+use super::StructOpt;
+use crate::command::{toml_string, Command};
+use crate::config::{ConfigPath, OpenConfig};
+use crate::desktop::{self, quote_exec};
+use crate::mime_helpers::{known_mimes, Detection, Specificity};
+use crate::mimeapps::{self, EXPORTED_PREFIX};
+
+/// Options to use for subcommand export
+#[derive(StructOpt, Debug)]
+pub struct ExportOptions {
+    #[structopt(subcommand)]
+    target: ExportTarget,
+}
+
+/// What rules can be exported to
+#[derive(StructOpt, Debug)]
+enum ExportTarget {
+    /// Export the open rules to desktop files and a mimeapps.list file
+    Mimeapps {
+        #[structopt(parse(from_os_str))]
+        /// the mimeapps.list file, ~/.config/mimeapps.list by default
+        path: Option<PathBuf>,
+
+        /// print what would change as a diff without writing anything
+        #[structopt(long)]
+        dry_run: bool,
+    },
+}
+
+impl Runable for ExportOptions {
+    fn run(self, config_path: &ConfigPath) -> Result<()> {
+        match self.target {
+            ExportTarget::Mimeapps { path, dry_run } => {
+                let path = match path {
+                    Some(path) => path,
+                    None => mimeapps::user_path()?,
+                };
+                export_mimeapps(&path, config_path, dry_run)
+            }
+        }
+    }
+}
+
+/// A file that will be written or removed by the export
+struct Change {
+    path: PathBuf,
+    old: Option<String>,
+    /// None if the file is removed
+    new: Option<String>,
+}
+
+/// Generates a desktop file for each command of the open rules and makes them the default
+/// applications for the mimes they open in the mimeapps.list file at `path`
+fn export_mimeapps(path: &Path, config_path: &ConfigPath, dry_run: bool) -> Result<()> {
+    let config = OpenConfig::load(config_path, None)?;
+    let applications_dir = desktop::user_applications_dir()?;
+    let exported_dir = exported_configs_dir()?;
+    let opener = env::current_exe().context("Failed to find the path of opener")?;
+
+    // every mime that the rules could match, the rules themselves and the mimes that they match
+    // through star mimes and subclasses
+    let mut mimes: BTreeSet<String> = config
+        .open
+        .iter()
+        .flat_map(|group| group.rules())
+        .map(|(mime, _)| mime.essence_str().to_string())
+        .filter(|mime| !mime.ends_with("/*"))
+        .collect();
+    mimes.extend(known_mimes().into_iter().map(str::to_string));
+
+    // the commands for each mime in the order they would be tried and every distinct command
+    let mut defaults: Vec<(String, Vec<Command>)> = Vec::new();
+    let mut commands: Vec<Command> = Vec::new();
+    for mime_str in mimes {
+        let mime: Mime = match mime_str.parse() {
+            Ok(mime) => mime,
+            Err(_) => continue,
+        };
+        let mut mime_commands: Vec<Command> = Vec::new();
+        for group in &config.open {
+            // a */* rule would take over every mime on the system, so it is left to xdg-open
+            let best = group
+                .ranked(&mime)
+                .into_iter()
+                .find(|(specificity, _, _)| *specificity != Specificity::AnyWildcard);
+            if let Some((_, _, command)) = best {
+                if !mime_commands.contains(command) {
+                    mime_commands.push(command.clone());
+                }
+            }
+        }
+        for command in &mime_commands {
+            if !commands.contains(command) {
+                commands.push(command.clone());
+            }
+        }
+        if !mime_commands.is_empty() {
+            defaults.push((mime_str, mime_commands));
+        }
+    }
+    if commands.is_empty() {
+        bail!("There are no open rules to export");
+    }
+
+    let ids = desktop_ids(&commands);
+    let mut changes = Vec::new();
+    for (command, id) in commands.iter().zip(&ids) {
+        // applications from desktop files are used as they are
+        if let Command::Desktop { .. } = command {
+            continue;
+        }
+        let mimes: Vec<&str> = defaults
+            .iter()
+            .filter(|(_, mime_commands)| mime_commands.contains(command))
+            .map(|(mime, _)| mime.as_str())
+            .collect();
+        // the desktop file opens the files with a config that only has the command, so that
+        // opener runs it the same way as for its rules
+        let config_file = exported_dir.join(format!("{}.toml", id.trim_end_matches(".desktop")));
+        changes.push(change(config_file.clone(), Some(exported_config(command, &config, id)))?);
+        let exec = [
+            opener.to_string_lossy(),
+            "--config".into(),
+            config_file.to_string_lossy(),
+            "open".into(),
+            "--".into(),
+        ]
+        .iter()
+        .map(|arg| quote_exec(arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+        let contents = format!(
+            "[Desktop Entry]\n\
+             Type=Application\n\
+             Name=opener: {}\n\
+             Comment=Generated by opener export, changes will be overwritten\n\
+             Exec={} %U\n\
+             NoDisplay=true\n\
+             MimeType={};\n",
+            name(command).replace('\n', " "),
+            exec,
+            mimes.join(";")
+        );
+        changes.push(change(applications_dir.join(id), Some(contents))?);
+    }
+
+    // desktop files and their configs from an earlier export whose command is not in the rules
+    // anymore
+    for (dir, extension) in [(&applications_dir, ".desktop"), (&exported_dir, ".toml")] {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let id = format!("{}.desktop", name.trim_end_matches(extension));
+            if name.starts_with(EXPORTED_PREFIX) && name.ends_with(extension) && !ids.contains(&id) {
+                changes.push(change(entry.path(), None)?);
+            }
+        }
+    }
+
+    let defaults: Vec<(String, Vec<String>)> = defaults
+        .into_iter()
+        .map(|(mime, mime_commands)| {
+            let mime_ids = mime_commands
+                .iter()
+                .map(|command| {
+                    let i = commands
+                        .iter()
+                        .position(|c| c == command)
+                        .expect("BUG: every command has an id");
+                    ids[i].clone()
+                })
+                .collect();
+            (mime, mime_ids)
+        })
+        .collect();
+    let old = read_optional(path)?;
+    let new = mimeapps::set_defaults(old.as_deref().unwrap_or_default(), &defaults, |id| {
+        id.starts_with(EXPORTED_PREFIX) && !ids.iter().any(|new_id| new_id == id)
+    });
+    changes.push(Change {
+        path: path.to_path_buf(),
+        old,
+        new: Some(new),
+    });
+
+    for change in changes.iter().filter(|change| change.old != change.new) {
+        if dry_run {
+            print!("{}", diff(change));
+            continue;
+        }
+        match &change.new {
+            Some(contents) => {
+                if let Some(dir) = change.path.parent() {
+                    fs::create_dir_all(dir)
+                        .context(format!("Failed to create the directory {}", dir.display()))?;
+                }
+                fs::write(&change.path, contents)
+                    .context(format!("Failed to write {}", change.path.display()))?;
+                println!("Wrote {}", change.path.display());
+            }
+            None => {
+                fs::remove_file(&change.path)
+                    .context(format!("Failed to remove {}", change.path.display()))?;
+                println!("Removed {}", change.path.display());
+            }
+        }
+    }
+    info!(
+        "Exported {} commands for {} mimes",
+        commands.len(),
+        defaults.len()
+    );
+
+    Ok(())
+}
+
+/// The directory with the configs that the generated desktop files open files with
+fn exported_configs_dir() -> Result<PathBuf> {
+    let base = BaseDirs::new().ok_or_else(|| anyhow!("Failed to find the data directory"))?;
+    Ok(base.data_dir().join("opener").join("export"))
+}
+
+/// The config that the desktop file `id` opens files with. It runs the command for every file
+/// with the settings from the config that was exported.
+fn exported_config(command: &Command, config: &OpenConfig, id: &str) -> String {
+    let mut contents = format!(
+        "# Generated by opener export for {}, changes will be overwritten\n",
+        id
+    );
+    let run_options = &config.run_options;
+    if run_options.detach {
+        contents.push_str("detach = true\n");
+    }
+    if let Some(log) = &run_options.detach_log {
+        let log = toml_string(&log.to_string_lossy());
+        contents.push_str(&format!("detach_log = {}\n", log));
+    }
+    if let Some(terminal_command) = &run_options.terminal_command {
+        let terminal_command = toml_string(terminal_command);
+        contents.push_str(&format!("terminal_command = {}\n", terminal_command));
+    }
+    if config.detection != Detection::default() {
+        let order: Vec<String> = config
+            .detection
+            .order
+            .iter()
+            .map(|detector| toml_string(&detector.to_string()))
+            .collect();
+        contents.push_str(&format!("detection = [{}]\n", order.join(", ")));
+    }
+    contents.push_str(&format!("\n[[open]]\n\"*/*\" = {}\n", command.to_toml()));
+    contents
+}
+
+/// Reads the current contents of a file that will be changed
+fn change(path: PathBuf, new: Option<String>) -> Result<Change> {
+    let old = read_optional(&path)?;
+    Ok(Change { path, old, new })
+}
+
+/// Reads a file, returning None if it does not exist
+fn read_optional(path: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).context(format!("Failed to read {}", path.display())),
+    }
+}
+
+/// The desktop file id of each command. Shell commands get an id made from their words like
+/// `opener-sxiv-a.desktop` for `sxiv -a`, and a number is added when two commands have the same
+/// name. Applications from desktop files keep their own id.
+fn desktop_ids(commands: &[Command]) -> Vec<String> {
+    let mut ids: Vec<String> = Vec::new();
+    for command in commands {
+        if let Command::Desktop { desktop } = command {
+            ids.push(desktop.clone());
+            continue;
+        }
+        let words: Vec<String> = name(command)
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+        let mut name = words.join("-");
+        name.truncate(40);
+        let name = name.trim_end_matches('-');
+        let name = if name.is_empty() { "command" } else { name };
+
+        let mut id = format!("{}{}.desktop", EXPORTED_PREFIX, name);
+        let mut n = 2;
+        while ids.contains(&id) {
+            id = format!("{}{}-{}.desktop", EXPORTED_PREFIX, name, n);
+            n += 1;
+        }
+        ids.push(id);
+    }
+    ids
+}
+
+/// A short name for a command that does not come from a desktop file, the program and its
+/// arguments without the options of a table
+fn name(command: &Command) -> String {
+    match command {
+        Command::Structured(command) => {
+            let mut words = vec![command.cmd.clone()];
+            words.extend(command.args.iter().cloned());
+            words.join(" ")
+        }
+        command => command.to_string(),
+    }
+}
+
+/// Shows how a file would change as a diff. Only the lines that change are shown with a few
+/// lines around them.
+fn diff(change: &Change) -> String {
+    const CONTEXT: usize = 2;
+
+    let old: Vec<&str> = change.old.as_deref().unwrap_or_default().lines().collect();
+    let new: Vec<&str> = change.new.as_deref().unwrap_or_default().lines().collect();
+
+    // the longest common subsequence of the lines, lcs[i][j] is for old[i..] and new[j..]
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines: Vec<(char, &str)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            // removed lines go before the lines that replace them
+            lines.push(('-', old[i]));
+            i += 1;
+        } else {
+            lines.push(('+', new[j]));
+            j += 1;
+        }
+    }
+
+    let name = |contents: &Option<String>| match contents {
+        Some(_) => change.path.display().to_string(),
+        None => "/dev/null".to_string(),
+    };
+    let mut output = format!("--- {}\n+++ {}\n", name(&change.old), name(&change.new));
+    let changed: Vec<usize> = (0..lines.len()).filter(|&k| lines[k].0 != ' ').collect();
+    let mut last_shown = None;
+    for (k, (marker, line)) in lines.iter().enumerate() {
+        let near_change = changed.iter().any(|&c| c + CONTEXT >= k && k + CONTEXT >= c);
+        if !near_change {
+            continue;
+        }
+        if last_shown.is_some_and(|last| last + 1 != k) {
+            output.push_str("...\n");
+        }
+        output.push(*marker);
+        output.push_str(line);
+        output.push('\n');
+        last_shown = Some(k);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell(cmd: &str) -> Command {
+        Command::Shell(cmd.to_string())
+    }
+
+    #[test]
+    fn desktop_ids_are_made_from_the_words_of_the_command() {
+        let commands = vec![
+            shell("sxiv -a %F"),
+            Command::Desktop {
+                desktop: "org.pwmt.zathura.desktop".to_string(),
+            },
+            shell("sxiv -a"),
+            Command::parse("{ cmd = \"mpv\", args = [\"--fs\"], detach = true }").unwrap(),
+            shell("%%"),
+            shell("SXIV_A"),
+        ];
+        assert_eq!(
+            desktop_ids(&commands),
+            [
+                "opener-sxiv-a-f.desktop",
+                "org.pwmt.zathura.desktop",
+                "opener-sxiv-a.desktop",
+                "opener-mpv-fs.desktop",
+                "opener-command.desktop",
+                "opener-sxiv-a-2.desktop",
+            ]
+        );
+    }
+
+    #[test]
+    fn desktop_ids_are_not_too_long() {
+        let ids = desktop_ids(&[shell(&"word ".repeat(20))]);
+        assert_eq!(
+            ids,
+            [format!(
+                "opener-{}.desktop",
+                "word-".repeat(8).trim_end_matches('-')
+            )]
+        );
+    }
+
+    fn change(old: Option<&str>, new: Option<&str>) -> Change {
+        Change {
+            path: PathBuf::from("/apps/a.desktop"),
+            old: old.map(str::to_string),
+            new: new.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn diff_shows_the_changed_lines_with_context() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\n2\n3\nfour\n5\n6\n7\n8\n9\nten\n";
+        assert_eq!(
+            diff(&change(Some(old), Some(new))),
+            "--- /apps/a.desktop\n+++ /apps/a.desktop\n 2\n 3\n-4\n+four\n 5\n 6\n...\n 8\n 9\n+ten\n"
+        );
+    }
+
+    #[test]
+    fn diff_of_a_new_or_removed_file_uses_dev_null() {
+        assert_eq!(
+            diff(&change(None, Some("a\n"))),
+            "--- /dev/null\n+++ /apps/a.desktop\n+a\n"
+        );
+        assert_eq!(
+            diff(&change(Some("a\n"), None)),
+            "--- /apps/a.desktop\n+++ /dev/null\n-a\n"
+        );
+    }
+
+    #[test]
+    fn exported_config_runs_the_command_with_the_settings() {
+        let mut config =
+            OpenConfig::load(&ConfigPath::explicit(PathBuf::from("/dev/null")), None).unwrap();
+        config.run_options.detach = true;
+        config.run_options.terminal_command = Some("foot".to_string());
+        let command = Command::parse("{ cmd = \"nvim\", terminal = true }").unwrap();
+        let contents = exported_config(&command, &config, "opener-nvim.desktop");
+        assert_eq!(
+            contents,
+            "# Generated by opener export for opener-nvim.desktop, changes will be overwritten\n\
+             detach = true\n\
+             terminal_command = \"foot\"\n\
+             \n\
+             [[open]]\n\
+             \"*/*\" = { cmd = \"nvim\", terminal = true }\n"
+        );
+        assert_eq!(
+            toml::from_str::<toml::Value>(&contents).unwrap()["open"][0]["*/*"]["cmd"].as_str(),
+            Some("nvim")
+        );
+    }
+}
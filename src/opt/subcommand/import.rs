@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use log::*;
+use mime::Mime;
+
+use super::Runable;
+use super::StructOpt;
+use crate::command::Command;
+use crate::config::{ConfigPath, EditConfig};
+use crate::desktop::DesktopEntry;
+use crate::mimeapps::{self, MimeApps};
+
+/// Options to use for subcommand import
+#[derive(StructOpt, Debug)]
+pub struct ImportOptions {
+    #[structopt(subcommand)]
+    source: ImportSource,
+}
+
+/// Where rules can be imported from
+#[derive(StructOpt, Debug)]
+enum ImportSource {
+    /// Import the applications in a mimeapps.list file
+    Mimeapps {
+        #[structopt(parse(from_os_str))]
+        /// the mimeapps.list file, ~/.config/mimeapps.list by default
+        path: Option<PathBuf>,
+
+        /// print the rules that would be imported without changing the config
+        #[structopt(long)]
+        dry_run: bool,
+    },
+}
+
+impl Runable for ImportOptions {
+    fn run(self, config_path: &ConfigPath) -> Result<()> {
+        match self.source {
+            ImportSource::Mimeapps { path, dry_run } => {
+                let path = match path {
+                    Some(path) => path,
+                    None => mimeapps::user_path()?,
+                };
+                import_mimeapps(&path, config_path, dry_run)
+            }
+        }
+    }
+}
+
+/// Imports the applications in the mimeapps.list file at `path` into the config. The default
+/// application of each mime goes in the first group that does not have the mime yet and the
+/// others become fall backs in the groups after it.
+fn import_mimeapps(path: &Path, config_path: &ConfigPath, dry_run: bool) -> Result<()> {
+    let mimeapps = MimeApps::load(path)?;
+    let mut cfg = EditConfig::load(config_path)?;
+
+    // the commands of the applications that could be found, for each mime
+    let mut rules = Vec::new();
+    for (mime, ids) in mimeapps.associations {
+        if let Err(e) = mime.parse::<Mime>() {
+            warn!("Skipping the invalid mime {} in {}: {}", mime, path.display(), e);
+            continue;
+        }
+
+        let mut commands = Vec::new();
+        for id in ids {
+            // the commands are compared and collapsed as they are written in the config
+            let command = DesktopEntry::find(&id)
+                .and_then(|entry| entry.to_command())
+                .map(|command| command.to_toml());
+            match command {
+                Ok(command) if !commands.contains(&command) => commands.push(command),
+                Ok(_) => (),
+                Err(e) => warn!("Skipping {} for {}: {:?}", id, mime, e),
+            }
+        }
+        if !commands.is_empty() {
+            rules.push((mime, commands));
+        }
+    }
+    if rules.is_empty() {
+        bail!("There were no applications to import from {}", path.display());
+    }
+
+    let mut imported = 0;
+    for (mime, commands) in collapse(rules) {
+        for command in commands {
+            if cfg.set(false, &mime, &Command::from_toml(&command)?)? {
+                imported += 1;
+                let verb = if dry_run { "Would import" } else { "Imported" };
+                println!("{} \"{}\" = {}", verb, mime, command);
+            }
+        }
+    }
+
+    if dry_run {
+        println!("Would import {} rules into {}", imported, config_path.display());
+    } else {
+        if imported > 0 {
+            cfg.store()?;
+        }
+        println!("Imported {} rules into {}", imported, config_path.display());
+    }
+
+    Ok(())
+}
+
+/// Replaces the mimes of a family like `image/png` and `image/jpeg` with a star mime like
+/// `image/*` when they have the same commands. Only the commands that the most mimes of the
+/// family share are collapsed, the other mimes keep their own rules which are more specific than
+/// the star mime. The rules are returned sorted by the mime.
+fn collapse(rules: Vec<(String, Vec<String>)>) -> Vec<(String, Vec<String>)> {
+    // how many mimes of each family have the same commands
+    let mut counts: HashMap<(String, Vec<String>), usize> = HashMap::new();
+    for (mime, commands) in &rules {
+        if let Some((family, subtype)) = mime.split_once('/') {
+            if subtype != "*" {
+                *counts
+                    .entry((family.to_string(), commands.clone()))
+                    .or_insert(0) += 1;
+            }
+        }
+    }
+
+    // the commands to collapse for each family, the ones shared by the most mimes
+    let mut shared: HashMap<String, (usize, Vec<String>)> = HashMap::new();
+    for ((family, commands), count) in counts {
+        // a family that already has a star mime is left alone
+        let star = format!("{}/*", family);
+        if count < 2 || rules.iter().any(|(mime, _)| *mime == star) {
+            continue;
+        }
+        let best = shared.entry(family).or_insert((0, Vec::new()));
+        if count > best.0 || (count == best.0 && commands < best.1) {
+            *best = (count, commands);
+        }
+    }
+
+    let mut collapsed: Vec<(String, Vec<String>)> = rules
+        .into_iter()
+        .filter(|(mime, commands)| {
+            let family = mime.split('/').next().unwrap_or_default();
+            shared
+                .get(family)
+                .is_none_or(|(_, shared)| shared != commands)
+        })
+        .collect();
+    for (family, (count, commands)) in shared {
+        debug!("Collapsing {} mimes of {} into {}/*", count, family, family);
+        collapsed.push((format!("{}/*", family), commands));
+    }
+    collapsed.sort();
+    collapsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &[(&str, &[&str])]) -> Vec<(String, Vec<String>)> {
+        rules
+            .iter()
+            .map(|(mime, commands)| {
+                (
+                    mime.to_string(),
+                    commands.iter().map(|c| c.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn collapse_shares_the_most_common_commands_of_a_family() {
+        let collapsed = collapse(rules(&[
+            ("image/png", &["sxiv"]),
+            ("image/jpeg", &["sxiv"]),
+            ("image/gif", &["sxiv"]),
+            ("image/svg+xml", &["inkscape"]),
+            ("image/webp", &["gimp"]),
+            ("image/tiff", &["gimp"]),
+            ("application/pdf", &["zathura"]),
+        ]));
+        assert_eq!(
+            collapsed,
+            rules(&[
+                ("application/pdf", &["zathura"]),
+                ("image/*", &["sxiv"]),
+                ("image/svg+xml", &["inkscape"]),
+                ("image/tiff", &["gimp"]),
+                ("image/webp", &["gimp"]),
+            ])
+        );
+    }
+
+    #[test]
+    fn collapse_needs_two_mimes_and_the_same_order_of_commands() {
+        let original = rules(&[
+            ("video/mp4", &["mpv", "vlc"]),
+            ("video/webm", &["vlc", "mpv"]),
+            ("audio/flac", &["mpv"]),
+        ]);
+        let mut sorted = original.clone();
+        sorted.sort();
+        assert_eq!(collapse(original), sorted);
+    }
+
+    #[test]
+    fn collapse_leaves_a_family_with_a_star_mime_alone() {
+        let original = rules(&[
+            ("text/*", &["less"]),
+            ("text/x-rust", &["nvim"]),
+            ("text/x-csrc", &["nvim"]),
+        ]);
+        let mut sorted = original.clone();
+        sorted.sort();
+        assert_eq!(collapse(original), sorted);
+    }
+}
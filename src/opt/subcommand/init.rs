@@ -0,0 +1,32 @@
+
+use anyhow::Result;
+
+use super::Runable;
+use super::StructOpt;
+use crate::config::{default_config, store_default, ConfigPath};
+
+/// Options to use for subcommand init
+#[derive(StructOpt, Debug)]
+pub struct InitOptions {
+    /// overwrite the config file if it already exists
+    #[structopt(long, short)]
+    force: bool,
+
+    /// print the default config instead of writing it
+    #[structopt(long, short)]
+    print: bool,
+}
+
+impl Runable for InitOptions {
+    fn run(self, config_path: &ConfigPath) -> Result<()> {
+        if self.print {
+            print!("{}", default_config());
+            return Ok(());
+        }
+
+        store_default(config_path, self.force)?;
+        println!("Wrote the default config to {}", config_path.display());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use serde_derive::Serialize;
+
+use super::Runable;
+use super::StructOpt;
+use crate::config::{ConfigPath, OpenConfig, RuleEntry};
+
+/// Options to use for subcommand list
+#[derive(StructOpt, Debug)]
+pub struct ListOptions {
+    /// the format to print the rules in: table, json, or toml
+    #[structopt(long, short, default_value = "table")]
+    format: Format,
+}
+
+/// The formats the rules can be printed in
+#[derive(Debug)]
+enum Format {
+    Table,
+    Json,
+    Toml,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "toml" => Ok(Format::Toml),
+            _ => bail!("Unknown format {}, expected table, json, or toml", s),
+        }
+    }
+}
+
+impl Runable for ListOptions {
+    fn run(self, config_path: &ConfigPath) -> Result<()> {
+        let rules = OpenConfig::load_rules(config_path, Some(Path::new(".")))?;
+
+        let output = match self.format {
+            Format::Table => to_table(&rules),
+            Format::Json => to_json(&rules)?,
+            Format::Toml => to_toml(&rules)?,
+        };
+        print!("{}", output);
+
+        Ok(())
+    }
+}
+
+/// Formats the rules as a table with aligned columns
+fn to_table(rules: &[RuleEntry]) -> String {
+    let header = ["SECTION", "GROUP", "PATTERN", "COMMAND", "STATUS", "SOURCE"];
+    let rows: Vec<[String; 6]> = rules
+        .iter()
+        .map(|rule| {
+            [
+                rule.section.clone(),
+                rule.group.to_string(),
+                rule.pattern.clone(),
+                rule.command.clone(),
+                match &rule.error {
+                    Some(e) => format!("invalid: {}", e),
+                    None => "ok".to_string(),
+                },
+                rule.layer.clone(),
+            ]
+        })
+        .collect();
+
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    let header = header.iter().map(|h| h.to_string()).collect::<Vec<_>>();
+    for row in std::iter::once(header.as_slice()).chain(rows.iter().map(|row| &row[..])) {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+/// Formats the rules as a json array of objects with the same fields as the toml tables
+fn to_json(rules: &[RuleEntry]) -> Result<String> {
+    Ok(format!("{}\n", serde_json::to_string_pretty(rules)?))
+}
+
+/// Formats the rules as a toml array of tables called rule
+fn to_toml(rules: &[RuleEntry]) -> Result<String> {
+    #[derive(Serialize)]
+    struct Rules<'a> {
+        rule: &'a [RuleEntry],
+    }
+
+    Ok(toml::to_string(&Rules { rule: rules })?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> Vec<RuleEntry> {
+        let rule = |pattern: &str, error: Option<&str>| RuleEntry {
+            section: "open".to_string(),
+            group: 1,
+            pattern: pattern.to_string(),
+            command: "sxiv \"%f\"".to_string(),
+            error: error.map(str::to_string),
+            layer: "/config.toml".to_string(),
+        };
+        vec![rule("image/png", None), rule("bad", Some("not a mime"))]
+    }
+
+    #[test]
+    fn json_and_toml_have_the_same_fields() {
+        let json: serde_json::Value = serde_json::from_str(&to_json(&rules()).unwrap()).unwrap();
+        let toml: toml::Value = toml::from_str(&to_toml(&rules()).unwrap()).unwrap();
+        let toml = toml["rule"].as_array().unwrap();
+        let json = json.as_array().unwrap();
+        assert_eq!(json.len(), 2);
+        for (json, toml) in json.iter().zip(toml) {
+            let mut json_keys: Vec<&String> = json.as_object().unwrap().keys().collect();
+            let mut toml_keys: Vec<&String> = toml.as_table().unwrap().keys().collect();
+            json_keys.sort();
+            toml_keys.sort();
+            assert_eq!(json_keys, toml_keys);
+            assert_eq!(json["command"].as_str(), toml["command"].as_str());
+        }
+        assert_eq!(json[0].get("error"), None);
+        assert_eq!(json[1]["error"], "not a mime");
+        assert_eq!(json[1]["source"], "/config.toml");
+    }
+
+    #[test]
+    fn no_rules_are_an_empty_array() {
+        assert_eq!(to_json(&[]).unwrap(), "[]\n");
+    }
+}
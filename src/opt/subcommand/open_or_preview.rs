@@ -1,91 +1,425 @@
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
 
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{bail, Context, Result};
 use log::*;
+use mime::Mime;
 use rayon::prelude::*;
-use regex::Regex;
-use subprocess::Exec;
 
 use super::Runable;
 use super::StructOpt;
-use crate::config::OpenConfig;
-use crate::mime_helpers::determine_mime;
+use crate::command::{check_detached, Command, RunOptions};
+use crate::config::{ConfigPath, Narrowable, OpenConfig};
+use crate::error::{print_error, print_warning};
+use crate::mime_helpers::content_mismatch;
+use crate::mimeapps;
+use crate::target::Target;
 
 /// Options to use for subcommand open
 #[derive(StructOpt, Debug)]
 pub struct OpenOptions {
-    #[structopt(parse(from_os_str))]
-    /// the file to open
-    path: PathBuf,
+    #[structopt(parse(from_os_str), required_unless_one = &["null", "from-file"])]
+    /// the files or URIs to open, files that are opened with the same command are given to it
+    /// together. - reads the paths from stdin, one on each line
+    paths: Vec<PathBuf>,
+
+    /// the paths from stdin or --from-file are separated by NUL like find -print0, stdin is read
+    /// even without -
+    #[structopt(short = "0", long)]
+    null: bool,
+
+    /// read the paths to open from this file, one on each line
+    #[structopt(long, parse(from_os_str))]
+    from_file: Option<PathBuf>,
 
-    /// enter interactive mode
+    /// choose the command from every rule that matches the files
     #[structopt(short, long)]
     interactive: bool,
 
-    /// preview the file
+    /// preview the files
     #[structopt(short, long)]
     preview: bool,
+
+    /// run the commands for different files at the same time
+    #[structopt(long)]
+    parallel: bool,
+}
+
+/// A file or URI that is being opened and the config that applies to it
+struct Resolved {
+    /// The path of the file or the URI
+    path: PathBuf,
+    mime: Mime,
+    /// The index of the config loaded for the directory of the file
+    config: usize,
+}
+
+/// Files that are opened with the same commands
+struct Batch<'a> {
+    /// The commands to try in order and where each one came from
+    commands: Vec<(Command, String)>,
+    targets: Vec<&'a Resolved>,
+    run_options: RunOptions,
+    /// Whether xdg-open is used for the files that every command failed to open
+    fall_back: bool,
 }
 
 impl Runable for OpenOptions {
-    fn run(self) -> Result<()> {
-        let open_config = OpenConfig::load()?;
+    fn run(self, config_path: &ConfigPath) -> Result<()> {
+        let paths = self.paths()?;
+        if paths.is_empty() {
+            bail!("There were no paths to open");
+        }
 
+        // each directory can have its own config, so they are loaded for each one
+        let mut dirs: Vec<Option<PathBuf>> = Vec::new();
+        let mut configs: Vec<OpenConfig> = Vec::new();
+        let mut targets = Vec::new();
+        // a path that cannot be opened does not stop the others from being opened
+        let mut errors = Vec::new();
+        for path in &paths {
+            match self.resolve(path, config_path, &mut dirs, &mut configs) {
+                Ok(target) => targets.push(target),
+                Err(e) => errors.push(e.context(format!("Failed to open {}", path.display()))),
+            }
+        }
+
+        let batches = if targets.is_empty() {
+            Vec::new()
+        } else if self.interactive {
+            self.choose(&targets, &configs)?
+        } else {
+            let mut batches: Vec<Batch> = Vec::new();
+            for target in &targets {
+                let commands = self.commands(target, &configs[target.config]);
+                let run_options = &configs[target.config].run_options;
+                let existing = batches.iter_mut().find(|batch| {
+                    batch.commands == commands && batch.run_options == *run_options
+                });
+                match existing {
+                    Some(batch) => batch.targets.push(target),
+                    None => batches.push(Batch {
+                        commands,
+                        targets: vec![target],
+                        run_options: run_options.clone(),
+                        fall_back: true,
+                    }),
+                }
+            }
+            batches
+        };
+
+        let batch_errors: Vec<anyhow::Error> = if self.parallel {
+            batches
+                .par_iter()
+                .filter_map(|batch| open_batch(batch).err())
+                .collect()
+        } else {
+            batches
+                .iter()
+                .filter_map(|batch| open_batch(batch).err())
+                .collect()
+        };
+        errors.extend(batch_errors);
+
+        let mut errors = errors.into_iter();
+        match (errors.next(), errors.len()) {
+            (None, _) => Ok(()),
+            (Some(e), 0) => Err(e),
+            (Some(e), more) => {
+                for e in std::iter::once(e).chain(errors) {
+                    print_error(format!("{:?}", e));
+                }
+                bail!("{} of the files or commands failed", more + 1)
+            }
+        }
+    }
+}
+
+impl OpenOptions {
+    /// The paths given as arguments, with `-` replaced by the paths read from stdin and the paths
+    /// read from `--from-file` after them
+    fn paths(&self) -> Result<Vec<PathBuf>> {
+        self.paths_with_stdin(&mut io::stdin())
+    }
+
+    /// Like `paths`, with the paths of `-` read from `stdin`
+    fn paths_with_stdin(&self, stdin: &mut impl Read) -> Result<Vec<PathBuf>> {
+        let dash = Path::new("-");
+        let mut paths = Vec::new();
+        for path in &self.paths {
+            if path == dash {
+                paths.extend(self.read_paths(&mut *stdin, "stdin")?);
+            } else {
+                paths.push(path.clone());
+            }
+        }
+        // -0 by itself reads from stdin like find -print0 | opener open -0
+        if self.null && self.from_file.is_none() && !self.paths.iter().any(|path| path == dash) {
+            paths.extend(self.read_paths(stdin, "stdin")?);
+        }
+        if let Some(list) = &self.from_file {
+            let file = fs::File::open(list)
+                .context(format!("Failed to open the list of paths {}", list.display()))?;
+            paths.extend(self.read_paths(file, &list.display().to_string())?);
+        }
+        Ok(paths)
+    }
+
+    /// Reads a list of paths separated by newlines, or by NUL with `-0`. Empty paths are skipped.
+    fn read_paths(&self, mut reader: impl Read, name: &str) -> Result<Vec<PathBuf>> {
+        let mut contents = Vec::new();
+        reader
+            .read_to_end(&mut contents)
+            .context(format!("Failed to read the paths from {}", name))?;
+        let separator = if self.null { b'\0' } else { b'\n' };
+        Ok(contents
+            .split(|byte| *byte == separator)
+            .filter(|path| !path.is_empty())
+            .map(|path| PathBuf::from(OsStr::from_bytes(path)))
+            .collect())
+    }
+
+    /// Finds out what the path is, loads the config for its directory if it was not loaded yet
+    /// and finds its mime
+    fn resolve(
+        &self,
+        path: &Path,
+        config_path: &ConfigPath,
+        dirs: &mut Vec<Option<PathBuf>>,
+        configs: &mut Vec<OpenConfig>,
+    ) -> Result<Resolved> {
+        let target = Target::parse(path)?;
+        let dir = target.dir().map(Path::to_path_buf);
+        let config = match dirs.iter().position(|d| *d == dir) {
+            Some(config) => config,
+            None => {
+                let mut config = OpenConfig::load(config_path, dir.as_deref())?;
+                config.run_options.preview = self.preview;
+                configs.push(config);
+                dirs.push(dir);
+                configs.len() - 1
+            }
+        };
+        let url_probe = configs[config].url_probe.as_ref();
+        let detection = &configs[config].detection;
+        let (mime, _source) = target.mime_with_source(url_probe, detection)?;
+        debug!("Guess for {}: {:?}", target, mime);
+        if let (true, Target::Path(path)) = (detection.warn_mismatch, &target) {
+            if let Some((extension, content)) = content_mismatch(path) {
+                print_warning(format!(
+                    "{} has the extension of {} but its contents are {}",
+                    path.display(),
+                    extension,
+                    content
+                ));
+            }
+        }
+        Ok(Resolved {
+            path: target.as_path().to_path_buf(),
+            mime,
+            config,
+        })
+    }
+
+    /// The command chosen by each group for the file, in the order they are tried. The regex
+    /// rules are tried first and then the mime rules.
+    fn commands(&self, target: &Resolved, config: &OpenConfig) -> Vec<(Command, String)> {
         let (possibilites, possible_regexes) = if self.preview {
-            let OpenConfig {
-                preview,
-                preview_regex,
-                ..
-            } = open_config;
-            (preview, preview_regex)
+            (&config.preview, &config.preview_regex)
         } else {
-            let OpenConfig {
-                open, open_regex, ..
-            } = open_config;
-            (open, open_regex)
+            (&config.open, &config.open_regex)
+        };
+
+        let mut commands = Vec::new();
+        if let Some(path_str) = path_str(&target.path) {
+            narrow_groups(&mut commands, possible_regexes, path_str);
+        }
+        narrow_groups(&mut commands, possibilites, &target.mime);
+        commands
+    }
+
+    /// Lets the user choose a command from every rule that matches any of the files and opens
+    /// all of them with it
+    fn choose<'a>(&self, targets: &'a [Resolved], configs: &[OpenConfig]) -> Result<Vec<Batch<'a>>> {
+        // every command that could open the files, regex rules first
+        let mut candidates = Vec::new();
+        for target in targets {
+            let config = &configs[target.config];
+            let (possibilites, possible_regexes) = if self.preview {
+                (&config.preview, &config.preview_regex)
+            } else {
+                (&config.open, &config.open_regex)
+            };
+            if let Some(path_str) = path_str(&target.path) {
+                collect_candidates(&mut candidates, possible_regexes, path_str);
+            }
+            collect_candidates(&mut candidates, possibilites, &target.mime);
+        }
+        debug!("Candidates to choose from: {:?}", candidates);
+
+        let config = &configs[targets[0].config];
+        let mut batch = Batch {
+            commands: Vec::new(),
+            targets: targets.iter().collect(),
+            run_options: config.run_options.clone(),
+            fall_back: false,
         };
+        if candidates.is_empty() {
+            info!("There were no commands to choose from");
+            batch.fall_back = true;
+            return Ok(vec![batch]);
+        }
 
-        let mime = determine_mime(&self.path)?;
-        debug!("Guess: {:?}", mime);
-
-        let path_str = self
-            .path
-            .to_str()
-            .ok_or(anyhow!("Failed to convert path to string"))?;
-
-        for possible_regex in possible_regexes {
-            let narrowed: Vec<(Regex, String)> = possible_regex
-                .into_par_iter()
-                .map(|(regex, command)| (Regex::new(&regex), command))
-                // TODO, needs to add warning if regex doesn't work
-                // filter errors
-                .filter_map(|(res, value)| res.ok().map(|regex| (regex, value)))
-                // filter the pairs that match the path str
-                .filter(|(regex, _command)| regex.is_match(path_str))
-                .collect();
-        }
-
-        // wheather and of the commands specified in config file was run succesfully
-        let mut command_successful = false;
-        for possible in possibilites {
-            // finds the correct command according to the mime
-            let command = possible.narrow(&mime);
-            if run_shell_command(&command, &self.path).is_ok() {
-                command_successful = true;
-                break;
+        let choices: Vec<String> = candidates.iter().map(Command::to_string).collect();
+        let choice = match config.chooser.choose(&choices)? {
+            Some(choice) => choice,
+            None => {
+                info!("Nothing was chosen");
+                return Ok(Vec::new());
             }
+        };
+        // the chooser may let the user type in a command of their own
+        let command = match candidates
+            .into_iter()
+            .find(|candidate| candidate.to_string() == choice)
+        {
+            Some(command) => command,
+            None => Command::parse(&choice)?,
+        };
+        batch.commands.push((command, "the chooser".to_string()));
+        Ok(vec![batch])
+    }
+}
+
+/// The path as a string for the regex rules, None if it is not valid UTF-8. Regexes cannot match
+/// such a path, so only the mime rules are used for it.
+fn path_str(path: &Path) -> Option<&str> {
+    let path_str = path.to_str();
+    if path_str.is_none() {
+        info!("Skipping the regex rules for {} because it is not valid UTF-8", path.display());
+    }
+    path_str
+}
+
+/// Adds the candidates of every group to `candidates` in order, skipping duplicates
+fn collect_candidates<N: Narrowable>(
+    candidates: &mut Vec<Command>,
+    groups: &[N],
+    compare: &N::Compare,
+) {
+    for candidate in groups.iter().flat_map(|group| group.candidates(compare)) {
+        if !candidates.contains(&candidate) {
+            candidates.push(candidate);
         }
+    }
+}
 
-        // if none of the commands were run succesfully or there were no commands specified, use
-        // xdg-open instead
-        if !command_successful {
-            info!("Using xdg-open instead");
-            xdg_open(&self.path)?;
+/// Narrows down each group to a command and adds them to `commands` with the layer they are from
+fn narrow_groups<N: Narrowable>(
+    commands: &mut Vec<(Command, String)>,
+    groups: &[N],
+    compare: &N::Compare,
+) {
+    // if nothing in a group matched, go to the next one
+    for group in groups {
+        if let Some(command) = group.narrow(compare) {
+            commands.push((command, group.layer().to_string()));
         }
+    }
+}
 
-        Ok(())
+/// Runs the commands of the batch in order until every file was opened. A command that fails
+/// falls through to the next one for the files it failed to open, and xdg-open is used for the
+/// files that none of the commands could open if the batch falls back to it.
+fn open_batch(batch: &Batch) -> Result<()> {
+    let mut remaining: Vec<&Resolved> = batch.targets.clone();
+    let mut last_error = None;
+    for (command, layer) in &batch.commands {
+        debug!("Using the rule from {}", layer);
+        let failures = run_command(command, &remaining, &batch.run_options);
+        remaining = Vec::new();
+        for (targets, e) in failures {
+            info!(
+                "{:?}, the rule is from {}, falling through to the next group",
+                e, layer
+            );
+            remaining.extend(targets);
+            last_error = Some(e);
+        }
+        if remaining.is_empty() {
+            return Ok(());
+        }
     }
+
+    if !batch.fall_back {
+        return match last_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        };
+    }
+
+    // none of the commands were run succesfully or there were no commands specified, use
+    // xdg-open instead
+    let mut looped = None;
+    for target in remaining {
+        // the desktop files from opener export run opener again, which would fall back to
+        // xdg-open again
+        if mimeapps::default_is_exported(target.mime.essence_str()) {
+            looped = Some(target);
+            continue;
+        }
+        info!("Using xdg-open instead for {}", target.path.display());
+        xdg_open(&target.path)?;
+    }
+    match (looped, last_error) {
+        (Some(target), Some(e)) => Err(e.context(format!(
+            "Not using xdg-open for {} because it would run opener again",
+            target.path.display()
+        ))),
+        (Some(target), None) => bail!(
+            "There is no rule for {} and xdg-open would run opener again",
+            target.path.display()
+        ),
+        (None, _) => Ok(()),
+    }
+}
+
+/// Runs the command with the files, all at once if it can take many files or once for each file.
+/// Returns the files of each run that failed and why.
+fn run_command<'a>(
+    command: &Command,
+    targets: &[&'a Resolved],
+    run_options: &RunOptions,
+) -> Vec<(Vec<&'a Resolved>, anyhow::Error)> {
+    let runs: Vec<Vec<&'a Resolved>> = if command.takes_many() {
+        vec![targets.to_vec()]
+    } else {
+        targets.iter().map(|target| vec![*target]).collect()
+    };
+
+    let mut failures = Vec::new();
+    let mut started = Vec::new();
+    for run in runs {
+        let paths: Vec<&Path> = run.iter().map(|target| target.path.as_path()).collect();
+        // the mime placeholder is the mime of the first file
+        match command.run(&paths, &run[0].mime, run_options) {
+            Ok(detached) => started.push((run, detached)),
+            Err(e) => failures.push((run, e)),
+        }
+    }
+
+    // the detached programs of all the runs get one grace period together instead of one each
+    let (runs, detached): (Vec<_>, Vec<_>) = started.into_iter().unzip();
+    for (run, result) in runs.into_iter().zip(check_detached(detached)) {
+        if let Err(e) = result {
+            failures.push((run, e));
+        }
+    }
+    failures
 }
 
 /// Open something using the default program on the system
@@ -94,18 +428,60 @@ fn xdg_open(path: impl AsRef<Path>) -> Result<()> {
     Ok(())
 }
 
-/// Run shell command and return Ok(()) if successful
-fn run_shell_command(cmd: &str, path: impl AsRef<Path>) -> Result<()> {
-    let path = path.as_ref();
-    let exit_status = Exec::shell(cmd).join()?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
 
-    if !exit_status.success() {
-        bail!(
-            "The child command {} with path {} failed",
-            cmd,
-            path.display()
-        );
+    fn paths(args: &[&str], stdin: &[u8]) -> Vec<PathBuf> {
+        let options =
+            OpenOptions::from_iter_safe(std::iter::once("open").chain(args.iter().copied()))
+                .unwrap();
+        options.paths_with_stdin(&mut &stdin[..]).unwrap()
     }
 
-    Ok(())
+    fn path_bufs(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn dash_is_replaced_by_the_lines_of_stdin() {
+        let read = paths(&["a", "-", "b"], b"one\n\ntwo words\n");
+        assert_eq!(read, path_bufs(&["a", "one", "two words", "b"]));
+        // stdin is only read without - when the paths are separated by NUL
+        assert_eq!(paths(&["a"], b"one\n"), path_bufs(&["a"]));
+    }
+
+    #[test]
+    fn null_separated_paths_can_have_newlines() {
+        let read = paths(&["-0"], b"one\nline\0two\0\0");
+        assert_eq!(read, path_bufs(&["one\nline", "two"]));
+        let read = paths(&["-0", "-", "a"], b"one\0");
+        assert_eq!(read, path_bufs(&["one", "a"]));
+    }
+
+    #[test]
+    fn paths_that_are_not_utf8_are_read_unchanged() {
+        let read = paths(&["-0"], b"caf\xe9\0");
+        assert_eq!(read, [PathBuf::from(OsStr::from_bytes(b"caf\xe9"))]);
+    }
+
+    #[test]
+    fn paths_from_a_file_go_after_the_arguments() {
+        let list = env::temp_dir().join(format!("opener-paths-{}.txt", process::id()));
+        fs::write(&list, "one\ntwo\n").unwrap();
+        let list_str = list.to_str().unwrap();
+        let read = paths(&["a", "--from-file", list_str], b"not read\n");
+        assert_eq!(read, path_bufs(&["a", "one", "two"]));
+        // with -0 the file is NUL separated and stdin is not read
+        fs::write(&list, "one\0two\n\0").unwrap();
+        let read = paths(&["-0", "--from-file", list_str], b"not read\0");
+        assert_eq!(read, path_bufs(&["one", "two\n"]));
+        fs::remove_file(&list).unwrap();
+
+        let options =
+            OpenOptions::from_iter_safe(&["open", "--from-file", "/no/such/list"]).unwrap();
+        assert!(options.paths_with_stdin(&mut &b""[..]).is_err());
+    }
 }
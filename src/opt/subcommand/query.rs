@@ -1,25 +1,25 @@
 use anyhow::{anyhow, Result};
-use log::*;
 use std::io::{stdout, Write};
 
 use super::parse_addtype;
 use super::ExtMimePath;
 use super::Runable;
 use super::StructOpt;
-use crate::mime_helpers::determine_mime;
+use crate::config::{ConfigPath, OpenConfig};
+use crate::mime_helpers::determine_mime_with_source;
 
 /// Options to use for subcommand query
 #[derive(StructOpt, Debug)]
 pub struct QueryOptions {
     /// Can be a file extension, path, or mime type If the argument is an extension or path, it
     /// prints the mime type associated with it. If The argument is a mime type, it prints out all
-    /// the extensions associated with it.
+    /// the extensions associated with it. Paths use the detection order of the config.
     #[structopt(parse(try_from_str = parse_addtype))]
     ext_mime_path: ExtMimePath,
 }
 
 impl Runable for QueryOptions {
-    fn run(self) -> Result<()> {
+    fn run(self, config_path: &ConfigPath) -> Result<()> {
         match self.ext_mime_path {
             ExtMimePath::Mime(mime) => {
                 let extensions = mime_guess::get_mime_extensions(&mime)
@@ -29,11 +29,13 @@ impl Runable for QueryOptions {
                 for extension in extensions {
                     write!(stdout, ".{} ", extension).unwrap();
                 }
-                write!(stdout, "\n").unwrap();
+                writeln!(stdout).unwrap();
                 stdout.flush().unwrap();
             }
             ExtMimePath::Path(path) => {
-                let mime_string = determine_mime(path)?.to_string();
+                let config = OpenConfig::load(config_path, path.parent())?;
+                let (mime, _source) = determine_mime_with_source(&path, &config.detection)?;
+                let mime_string = mime.to_string();
                 println!("{}", mime_string);
             }
             ExtMimePath::Extension(ext) => {
@@ -0,0 +1,184 @@
+use anyhow::{bail, Result};
+use log::*;
+
+use super::parse_addtype;
+use super::ExtMimePath;
+use super::Runable;
+use super::StructOpt;
+use crate::command::Command;
+use crate::config::{mime_key, ConfigPath, EditConfig};
+
+/// Options to use for subcommand unset
+#[derive(StructOpt, Debug)]
+pub struct UnsetOptions {
+    /// can be a file extension, mime, or path
+    #[structopt(parse(try_from_str = parse_addtype))]
+    ext_mime_path: ExtMimePath,
+
+    /// weather to unset preview instead of unsetting the open command
+    #[structopt(long, short)]
+    preview: bool,
+
+    /// only remove the rule if it has this command, tables like '{ cmd = "mpv" }' match however
+    /// they are written
+    #[structopt(long, short)]
+    command: Option<String>,
+
+    /// only remove the rule from this group, starting at 0 for the first table
+    #[structopt(long, short)]
+    group: Option<usize>,
+
+    /// print what would be removed without changing the config file
+    #[structopt(long)]
+    dry_run: bool,
+}
+
+impl Runable for UnsetOptions {
+    fn run(self, config_path: &ConfigPath) -> Result<()> {
+        let mut cfg = EditConfig::load(config_path)?;
+        debug!("Run unset is using this config:\n{}", cfg);
+
+        let mime = self.ext_mime_path.into_mime(config_path)?;
+        let mime_str = mime.as_ref();
+        let (array, array_name) = if self.preview {
+            (cfg.get_preview()?, "preview")
+        } else {
+            (cfg.get_open()?, "open")
+        };
+
+        let wanted = self.command.as_deref().map(Command::parse).transpose()?;
+
+        // the indexes of the tables that became empty, removed after the loop so the indexes of the
+        // other tables do not change while iterating
+        let mut emptied = Vec::new();
+        let mut removed_any = false;
+        for idx in 0..array.len() {
+            if self.group.is_some_and(|group| group != idx) {
+                continue;
+            }
+            let table = array.get_mut(idx).expect("BUG: index should be in bounds");
+            // the rule is found by the parsed mime, so `text/plain;charset=utf-8` finds
+            // `text/plain; charset=utf-8` but not `text/plain`
+            let key = match mime_key(table, mime_str) {
+                Some(key) => key,
+                None => continue,
+            };
+
+            // commands can be strings or inline tables like { desktop = "..." }
+            let (command, shown) = match table.get(&key) {
+                Some(item) if item.as_str().is_some() => {
+                    let command = item.as_str().unwrap_or_default().to_string();
+                    let shown = format!("\"{}\"", command);
+                    (Ok(Command::Shell(command)), shown)
+                }
+                Some(item) if item.is_value() => {
+                    let shown = item.as_value().map(|v| v.to_string()).unwrap_or_default();
+                    let shown = shown.trim().to_string();
+                    (Command::from_toml(&shown), shown)
+                }
+                _ => continue,
+            };
+            // tables are compared by what they contain rather than how they are written
+            if wanted.is_some() && command.ok() != wanted {
+                debug!("Skipping {} in group {} with command {}", key, idx, shown);
+                continue;
+            }
+
+            if self.dry_run {
+                println!(
+                    "Would remove \"{}\" = {} from [[{}]] group {}",
+                    key, shown, array_name, idx
+                );
+            } else {
+                table.remove(&key);
+                println!(
+                    "Removed \"{}\" = {} from [[{}]] group {}",
+                    key, shown, array_name, idx
+                );
+            }
+            removed_any = true;
+
+            if table.is_empty() {
+                emptied.push(idx);
+            }
+        }
+
+        if !removed_any {
+            bail!("No rule for {} was found in [[{}]]", mime_str, array_name);
+        }
+
+        if self.dry_run {
+            return Ok(());
+        }
+
+        for idx in emptied.into_iter().rev() {
+            info!("Removing group {} because it is empty", idx);
+            array.remove(idx);
+        }
+        cfg.store()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    /// Unsets the rule for the mime in a config with the source, returns the config afterwards or
+    /// None if unset failed
+    fn unset(name: &str, source: &str, mime: &str, command: Option<&str>) -> Option<String> {
+        let path = env::temp_dir().join(format!("opener-unset-{}-{}.toml", process::id(), name));
+        fs::write(&path, source).unwrap();
+        let options = UnsetOptions {
+            ext_mime_path: parse_addtype(mime).unwrap(),
+            preview: false,
+            command: command.map(str::to_string),
+            group: None,
+            dry_run: false,
+        };
+        let result = options.run(&ConfigPath::explicit(path.clone()));
+        let after = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        result.ok().map(|_| after)
+    }
+
+    #[test]
+    fn unset_finds_a_rule_with_parameters_however_it_is_written() {
+        let source =
+            "[[open]]\n\"text/plain; charset=utf-8\" = \"less\"\n\"text/plain\" = \"vim\"\n";
+        let after = unset("params", source, "text/plain;charset=UTF-8", None).unwrap();
+        assert_eq!(after, "[[open]]\n\"text/plain\" = \"vim\"\n");
+    }
+
+    #[test]
+    fn unset_without_parameters_keeps_the_rules_with_parameters() {
+        let source =
+            "[[open]]\n\"text/plain; charset=utf-8\" = \"less\"\n\"text/plain\" = \"vim\"\n";
+        let after = unset("no-params", source, "text/plain", None).unwrap();
+        assert_eq!(
+            after,
+            "[[open]]\n\"text/plain; charset=utf-8\" = \"less\"\n"
+        );
+    }
+
+    #[test]
+    fn unset_fails_when_the_parameters_differ() {
+        let source = "[[open]]\n\"text/plain; charset=utf-8\" = \"less\"\n";
+        assert_eq!(
+            unset("other-params", source, "text/plain; charset=latin1", None),
+            None
+        );
+    }
+
+    #[test]
+    fn unset_only_removes_the_wanted_command_and_empty_groups() {
+        let source =
+            "[[open]]\n\"image/png\" = \"feh\"\n\n[[open]]\n\"image/png\" = { cmd = \"gimp\" }\n";
+        let after = unset("command", source, "image/png", Some("{cmd='gimp'}")).unwrap();
+        assert_eq!(after, "[[open]]\n\"image/png\" = \"feh\"\n");
+    }
+}
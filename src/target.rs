@@ -0,0 +1,253 @@
+mod probe;
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::*;
+use mime::Mime;
+
+use crate::mime_helpers::{determine_mime_with_source, Detection, MimeSource};
+
+pub use probe::UrlProbe;
+
+/// The pseudo mime type of URIs is this followed by the scheme, like `x-scheme-handler/https`
+const SCHEME_HANDLER: &str = "x-scheme-handler/";
+
+/// The schemes of URLs whose mime type can be found from the URL or by probing it
+const HTTP_SCHEMES: &[&str] = &["http", "https"];
+
+/// Something that can be opened, a file or a URI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Path(PathBuf),
+    /// A URI like `https://example.com` or `mailto:x@y` that is not a local file
+    Uri { uri: String, scheme: String },
+}
+
+impl Target {
+    /// Finds out what an argument refers to. A file that exists is always a path, even if it
+    /// looks like a URI. `file://` URIs are decoded to the path they point to.
+    pub fn parse(arg: &Path) -> Result<Target> {
+        let uri = match arg.to_str() {
+            Some(uri) if !arg.exists() => uri,
+            _ => return Ok(Target::Path(arg.to_path_buf())),
+        };
+        let scheme = match scheme(uri) {
+            Some(scheme) => scheme.to_ascii_lowercase(),
+            None => return Ok(Target::Path(arg.to_path_buf())),
+        };
+
+        if scheme == "file" {
+            return Ok(Target::Path(file_uri_path(uri)?));
+        }
+        Ok(Target::Uri {
+            uri: uri.to_string(),
+            scheme,
+        })
+    }
+
+    /// What commands are given to open the target, the path or the URI
+    pub fn as_path(&self) -> &Path {
+        match self {
+            Target::Path(path) => path,
+            Target::Uri { uri, .. } => Path::new(uri),
+        }
+    }
+
+    /// The directory whose config applies to the target, None for URIs
+    pub fn dir(&self) -> Option<&Path> {
+        match self {
+            Target::Path(path) => path.parent(),
+            Target::Uri { .. } => None,
+        }
+    }
+
+    /// The mime type of the target and how it was determined. URIs have a pseudo mime type for
+    /// their scheme like `x-scheme-handler/https`. For http and https URLs the extension of the
+    /// URL is tried first and then the `url_probe` command if there is one, and the scheme is
+    /// only used if neither of them work. Files use the detection of the config.
+    pub fn mime_with_source(
+        &self,
+        url_probe: Option<&UrlProbe>,
+        detection: &Detection,
+    ) -> Result<(Mime, MimeSource)> {
+        match self {
+            Target::Path(path) => determine_mime_with_source(path, detection),
+            Target::Uri { uri, scheme } => {
+                if HTTP_SCHEMES.contains(&scheme.as_str()) {
+                    if let Some(mime) = mime_guess::from_path(url_path(uri)).first() {
+                        return Ok((mime, MimeSource::Extension));
+                    }
+                    if let Some(url_probe) = url_probe {
+                        match probe::probe(uri, url_probe) {
+                            Ok(mime) => return Ok((mime, MimeSource::Probe)),
+                            Err(e) => info!("{:?}, using the scheme of {} instead", e, uri),
+                        }
+                    }
+                }
+
+                let mime = format!("{}{}", SCHEME_HANDLER, scheme);
+                let mime = mime
+                    .parse::<Mime>()
+                    .context(format!("The scheme {} is not a valid mime type", scheme))?;
+                Ok((mime, MimeSource::Scheme))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Path(path) => write!(f, "{}", path.display()),
+            Target::Uri { uri, .. } => write!(f, "{}", uri),
+        }
+    }
+}
+
+/// Whether a path given to a command is a URI rather than a file
+pub fn is_uri(path: &Path) -> bool {
+    matches!(Target::parse(path), Ok(Target::Uri { .. }))
+}
+
+/// The scheme of a URI like `https` for `https://example.com`. Schemes need at least two
+/// characters so that a path like `C:` is not read as a URI.
+fn scheme(uri: &str) -> Option<&str> {
+    let (scheme, _) = uri.split_once(':')?;
+    let mut chars = scheme.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || "+.-".contains(c))
+        && scheme.len() >= 2;
+    if valid {
+        Some(scheme)
+    } else {
+        None
+    }
+}
+
+/// The path of a URL without the query and fragment, like `/a/b.mp4` for
+/// `https://example.com/a/b.mp4?t=1`
+fn url_path(url: &str) -> &str {
+    let after_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let end = after_scheme.find(['?', '#']).unwrap_or(after_scheme.len());
+    let before_query = &after_scheme[..end];
+    before_query.find('/').map_or("", |i| &before_query[i..])
+}
+
+/// Decodes a `file://` URI to a path. The host has to be empty or localhost.
+fn file_uri_path(uri: &str) -> Result<PathBuf> {
+    // the scheme is case insensitive
+    let rest = &uri["file:".len()..];
+    let path = match rest.strip_prefix("//") {
+        Some(authority) => {
+            let (host, path) = match authority.find('/') {
+                Some(i) => authority.split_at(i),
+                None => (authority, "/"),
+            };
+            if !host.is_empty() && !host.eq_ignore_ascii_case("localhost") {
+                bail!("The file URI {} is on another host, {}", uri, host);
+            }
+            path
+        }
+        None => rest,
+    };
+
+    // percent encoded bytes like %20 are decoded, a % that is not followed by two hex digits is
+    // kept as it is
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        match after.get(..2) {
+            Some(hex) if byte == b'%' && hex.iter().all(u8::is_ascii_hexdigit) => {
+                let hex = std::str::from_utf8(hex).expect("BUG: hex digits are ascii");
+                bytes.push(u8::from_str_radix(hex, 16).expect("BUG: checked the hex digits"));
+                rest = &after[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = after;
+            }
+        }
+    }
+    Ok(PathBuf::from(OsStr::from_bytes(&bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scheme_needs_a_valid_name_before_the_colon() {
+        assert_eq!(scheme("https://example.com"), Some("https"));
+        assert_eq!(scheme("mailto:x@y"), Some("mailto"));
+        assert_eq!(scheme("git+ssh://host/repo"), Some("git+ssh"));
+        assert_eq!(scheme("C:/Users"), None);
+        assert_eq!(scheme("1ab:x"), None);
+        assert_eq!(scheme("a b:x"), None);
+        assert_eq!(scheme("./a:b"), None);
+        assert_eq!(scheme("no colon"), None);
+    }
+
+    #[test]
+    fn url_path_leaves_out_the_host_query_and_fragment() {
+        assert_eq!(url_path("https://example.com/a/b.mp4?t=1#x"), "/a/b.mp4");
+        assert_eq!(url_path("https://example.com/a.png#top"), "/a.png");
+        assert_eq!(url_path("https://example.com"), "");
+        assert_eq!(url_path("https://example.com?a=/b.png"), "");
+    }
+
+    #[test]
+    fn file_uri_path_decodes_the_path() {
+        let path = |uri| file_uri_path(uri).unwrap();
+        assert_eq!(path("file:///tmp/a%20b.txt"), Path::new("/tmp/a b.txt"));
+        assert_eq!(path("file://localhost/tmp/a"), Path::new("/tmp/a"));
+        assert_eq!(path("FILE://LocalHost/tmp/a"), Path::new("/tmp/a"));
+        assert_eq!(path("file:/tmp/a"), Path::new("/tmp/a"));
+        assert_eq!(path("file://"), Path::new("/"));
+        assert_eq!(path("file:///100%25"), Path::new("/100%"));
+    }
+
+    #[test]
+    fn file_uri_path_keeps_percent_signs_that_are_not_escapes() {
+        let path = |uri| file_uri_path(uri).unwrap();
+        assert_eq!(path("file:///100%"), Path::new("/100%"));
+        assert_eq!(path("file:///a%zzb"), Path::new("/a%zzb"));
+        assert_eq!(path("file:///a%2"), Path::new("/a%2"));
+    }
+
+    #[test]
+    fn file_uri_path_decodes_bytes_that_are_not_utf8() {
+        assert_eq!(
+            file_uri_path("file:///a%FF").unwrap(),
+            Path::new(OsStr::from_bytes(b"/a\xff"))
+        );
+    }
+
+    #[test]
+    fn file_uri_path_fails_for_other_hosts() {
+        assert!(file_uri_path("file://example.com/tmp/a").is_err());
+    }
+
+    #[test]
+    fn parse_finds_uris_that_are_not_files() {
+        assert_eq!(
+            Target::parse(Path::new("HTTPS://example.com")).unwrap(),
+            Target::Uri {
+                uri: "HTTPS://example.com".to_string(),
+                scheme: "https".to_string(),
+            }
+        );
+        assert_eq!(
+            Target::parse(Path::new("file:///tmp/a%20b")).unwrap(),
+            Target::Path(PathBuf::from("/tmp/a b"))
+        );
+        assert_eq!(
+            Target::parse(Path::new("/no/such/file.txt")).unwrap(),
+            Target::Path(PathBuf::from("/no/such/file.txt"))
+        );
+        assert!(!is_uri(Path::new(".")));
+    }
+}
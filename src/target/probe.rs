@@ -0,0 +1,363 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use log::*;
+use mime::Mime;
+use subprocess::{Exec, Redirection};
+
+use crate::command::quote;
+use crate::xdg;
+
+/// The file in the cache directory with the mime type of every URL that was probed
+const CACHE_FILE: &str = "url_mimes";
+
+/// How long a probed mime is used for if `url_probe_ttl` is not set, one day
+const DEFAULT_TTL: u64 = 24 * 60 * 60;
+
+/// The command that finds the mime type of http URLs and where its results are cached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UrlProbe {
+    /// The shell command, the URL is added to the end of it
+    pub command: String,
+    /// The file the results are cached in, None if they are not cached
+    pub cache: Option<PathBuf>,
+    /// How long a cached result is used before the URL is probed again
+    pub ttl: Duration,
+}
+
+impl UrlProbe {
+    /// Creates the probe from the config. The cache defaults to `$XDG_CACHE_HOME/opener/url_mimes`
+    /// and the ttl, in seconds, to one day. A ttl of 0 turns the cache off.
+    pub fn new(command: String, cache: Option<PathBuf>, ttl: Option<u64>) -> UrlProbe {
+        let ttl = Duration::from_secs(ttl.unwrap_or(DEFAULT_TTL));
+        let cache = cache
+            .or_else(|| xdg::cache_dir().ok().map(|dir| dir.join(CACHE_FILE)))
+            .filter(|_| !ttl.is_zero());
+        UrlProbe {
+            command,
+            cache,
+            ttl,
+        }
+    }
+
+    /// The cached mime of the URL, None if it was not probed with this command within the ttl
+    fn cached(&self, url: &str, now: u64) -> Option<Mime> {
+        let path = self.cache.as_ref()?;
+        let cache = read_cache(path)?;
+        // later lines are newer
+        cache
+            .lines()
+            .rev()
+            .filter_map(Entry::parse)
+            .filter(|entry| self.is_fresh(entry, now))
+            .filter(|entry| entry.command == self.command && entry.url == url)
+            .find_map(|entry| entry.mime.parse().ok())
+    }
+
+    /// Adds the mime of the URL to the cache and drops the entries that expired
+    fn store(&self, url: &str, mime: &Mime, now: u64) -> Result<()> {
+        let path = match &self.cache {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        // a tab or a newline would break the cache file
+        if url.contains(['\t', '\n']) || self.command.contains(['\t', '\n']) {
+            return Ok(());
+        }
+
+        let cache = read_cache(path).unwrap_or_default();
+        let mut lines: Vec<String> = cache
+            .lines()
+            .filter(|line| Entry::parse(line).is_some_and(|entry| self.is_fresh(&entry, now)))
+            .map(str::to_string)
+            .collect();
+        lines.push(format!("{}\t{}\t{}\t{}", now, self.command, url, mime));
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .context(format!("Failed to create the directory {}", dir.display()))?;
+        }
+        fs::write(path, lines.join("\n") + "\n")
+            .context(format!("Failed to write to the cache {}", path.display()))
+    }
+
+    /// Whether the entry was probed within the ttl. Entries from the future are treated as
+    /// expired so a clock that was wrong does not keep them forever.
+    fn is_fresh(&self, entry: &Entry, now: u64) -> bool {
+        entry.time <= now && now - entry.time < self.ttl.as_secs()
+    }
+}
+
+/// Finds the mime type of a URL by running the probe command with it and reading the
+/// `Content-Type` header from its output, like `curl -sIL` prints. The result is cached for the
+/// ttl of the probe, so a URL is only probed again once its result is older than that.
+pub fn probe(url: &str, probe: &UrlProbe) -> Result<Mime> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if let Some(mime) = probe.cached(url, now) {
+        debug!("Using the cached mime {} for {}", mime, url);
+        return Ok(mime);
+    }
+
+    let cmd = format!("{} {}", probe.command, quote(url));
+    debug!("Probing {} with {}", url, cmd);
+    let capture = Exec::shell(&cmd)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()
+        .context(format!("Failed to run the probe command {}", cmd))?;
+    if !capture.success() {
+        bail!(
+            "The probe command {} failed: {}",
+            cmd,
+            capture.stderr_str().trim()
+        );
+    }
+
+    let mime = content_type(&capture.stdout_str())
+        .context(format!("Failed to find the mime of {} with {}", url, cmd))?;
+    if let Err(e) = probe.store(url, &mime, now) {
+        warn!("Failed to cache the mime of {}: {:?}", url, e);
+    }
+    Ok(mime)
+}
+
+/// The mime type in the `Content-Type` header of the last response in the headers. When redirects
+/// are followed there is a set of headers for each response and the last one is for the resource
+/// itself, the type of a redirect is not used for it. An error response like 404 has the type of
+/// the error page, so it is not used either.
+fn content_type(headers: &str) -> Result<Mime> {
+    // the headers of the last response start at its status line
+    let lines: Vec<&str> = headers.lines().collect();
+    let start = lines
+        .iter()
+        .rposition(|line| line.starts_with("HTTP/"))
+        .unwrap_or(0);
+    let last = &lines[start..];
+
+    let status = last
+        .first()
+        .filter(|line| line.starts_with("HTTP/"))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse::<u16>().ok());
+    if let Some(status) = status.filter(|status| *status >= 400) {
+        bail!("The URL responded with the status {}", status);
+    }
+
+    let value = last
+        .iter()
+        .rev()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-type"))
+        .map(|(_, value)| value.trim())
+        .context("There is no Content-Type header")?;
+    value
+        .parse::<Mime>()
+        .context(format!("The Content-Type {} is not a valid mime type", value))
+}
+
+/// A line of the cache file. Each line has the time the URL was probed in seconds since the
+/// epoch, the probe command, the URL and its mime, separated by tabs.
+struct Entry<'a> {
+    time: u64,
+    command: &'a str,
+    url: &'a str,
+    mime: &'a str,
+}
+
+impl<'a> Entry<'a> {
+    /// Parses a line of the cache, None if it is not a valid entry
+    fn parse(line: &'a str) -> Option<Entry<'a>> {
+        let mut fields = line.splitn(4, '\t');
+        Some(Entry {
+            time: fields.next()?.parse().ok()?,
+            command: fields.next()?,
+            url: fields.next()?,
+            mime: fields.next()?,
+        })
+    }
+}
+
+/// The contents of the cache, None if there is no cache yet or it cannot be read
+fn read_cache(path: &Path) -> Option<String> {
+    match fs::read_to_string(path) {
+        Ok(cache) => Some(cache),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => {
+            warn!("Failed to read the cache {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    /// An empty directory for a test to keep its cache and stand-in probe in
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("opener-probe-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A probe that prints the headers like `curl -sIL` and logs every URL it is run with
+    fn stand_in(dir: &Path, headers: &str, ttl: u64) -> UrlProbe {
+        let script = dir.join("probe.sh");
+        let source = format!(
+            "echo \"$1\" >> {}\nprintf '{}'\n",
+            quote(&dir.join("probed").to_string_lossy()),
+            headers.replace('\n', "\\r\\n")
+        );
+        fs::write(&script, source).unwrap();
+        UrlProbe::new(
+            format!("sh {}", quote(&script.to_string_lossy())),
+            Some(dir.join("cache")),
+            Some(ttl),
+        )
+    }
+
+    /// The URLs the stand-in was run with
+    fn probed(dir: &Path) -> Vec<String> {
+        fs::read_to_string(dir.join("probed"))
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn content_type_uses_the_last_response() {
+        let headers = "HTTP/1.1 301 Moved Permanently\r\n\
+                       Content-Type: text/html\r\n\
+                       Location: /video\r\n\
+                       \r\n\
+                       HTTP/2 200\r\n\
+                       content-type: video/mp4\r\n\
+                       \r\n";
+        assert_eq!(
+            content_type(headers).unwrap(),
+            "video/mp4".parse::<Mime>().unwrap()
+        );
+    }
+
+    #[test]
+    fn content_type_does_not_use_the_type_of_a_redirect() {
+        let headers = "HTTP/1.1 301 Moved Permanently\r\n\
+                       Content-Type: text/html\r\n\
+                       Location: /video\r\n\
+                       \r\n\
+                       HTTP/1.1 200 OK\r\n\
+                       Content-Length: 10\r\n\
+                       \r\n";
+        assert!(content_type(headers).is_err());
+    }
+
+    #[test]
+    fn content_type_keeps_the_parameters() {
+        let mime = content_type("HTTP/1.1 200 OK\nContent-Type:  text/html; charset=utf-8\n");
+        assert_eq!(mime.unwrap().essence_str(), "text/html");
+    }
+
+    #[test]
+    fn content_type_fails_for_error_responses() {
+        let headers = "HTTP/1.1 302 Found\nLocation: /gone\n\nHTTP/1.1 404 Not Found\n\
+                       Content-Type: text/html\n";
+        assert!(content_type(headers).is_err());
+    }
+
+    #[test]
+    fn content_type_fails_without_a_valid_header() {
+        assert!(content_type("HTTP/1.1 200 OK\nContent-Length: 10\n").is_err());
+        assert!(content_type("HTTP/1.1 200 OK\nContent-Type: nonsense\n").is_err());
+        assert!(content_type("").is_err());
+    }
+
+    #[test]
+    fn probe_caches_the_mime_of_each_url() {
+        let dir = test_dir("cache");
+        let stand_in = stand_in(&dir, "HTTP/1.1 200 OK\nContent-Type: video/webm\n\n", 60);
+        let url = "https://example.com/watch?v=1";
+        for _ in 0..2 {
+            assert_eq!(
+                probe(url, &stand_in).unwrap(),
+                "video/webm".parse::<Mime>().unwrap()
+            );
+        }
+        probe("https://example.com/other", &stand_in).unwrap();
+        assert_eq!(probed(&dir), [url, "https://example.com/other"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn probe_does_not_use_the_results_of_other_commands() {
+        let dir = test_dir("commands");
+        let stand_in = stand_in(&dir, "HTTP/1.1 200 OK\nContent-Type: video/webm\n\n", 60);
+        let url = "https://example.com/watch?v=1";
+        probe(url, &stand_in).unwrap();
+        let other = UrlProbe {
+            command: format!("{} --other", stand_in.command),
+            ..stand_in
+        };
+        probe(url, &other).unwrap();
+        assert_eq!(probed(&dir).len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn probe_ignores_expired_and_old_entries() {
+        let dir = test_dir("expired");
+        let stand_in = stand_in(&dir, "HTTP/1.1 200 OK\nContent-Type: video/webm\n\n", 60);
+        let url = "https://example.com/watch?v=1";
+        let cache = format!(
+            "{}\nhttps://example.com/watch?v=1\ttext/html\n0\t{}\t{}\ttext/html\n",
+            "not an entry", stand_in.command, url
+        );
+        fs::write(dir.join("cache"), cache).unwrap();
+
+        assert_eq!(
+            probe(url, &stand_in).unwrap(),
+            "video/webm".parse::<Mime>().unwrap()
+        );
+        assert_eq!(probed(&dir), [url]);
+        // the expired entries are dropped when the new one is stored
+        let cache = fs::read_to_string(dir.join("cache")).unwrap();
+        assert_eq!(cache.lines().count(), 1);
+        assert!(cache.ends_with(&format!("\t{}\t{}\tvideo/webm\n", stand_in.command, url)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn probe_without_a_ttl_does_not_cache() {
+        let dir = test_dir("no-ttl");
+        let stand_in = stand_in(&dir, "HTTP/1.1 200 OK\nContent-Type: video/webm\n\n", 0);
+        assert_eq!(stand_in.cache, None);
+        let url = "https://example.com/watch?v=1";
+        probe(url, &stand_in).unwrap();
+        probe(url, &stand_in).unwrap();
+        assert_eq!(probed(&dir), [url, url]);
+        assert!(!dir.join("cache").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn probe_fails_and_caches_nothing_for_error_responses() {
+        let dir = test_dir("not-found");
+        let stand_in = stand_in(
+            &dir,
+            "HTTP/1.1 404 Not Found\nContent-Type: text/html\n\n",
+            60,
+        );
+        assert!(probe("https://example.com/gone", &stand_in).is_err());
+        assert!(!dir.join("cache").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
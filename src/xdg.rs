@@ -0,0 +1,47 @@
+use std::env;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use directories::BaseDirs;
+
+/// The value of `XDG_DATA_DIRS` if it is not set
+const DEFAULT_DATA_DIRS: &str = "/usr/local/share:/usr/share";
+
+/// The value of `XDG_CONFIG_DIRS` if it is not set
+const DEFAULT_CONFIG_DIRS: &str = "/etc/xdg";
+
+/// The data directories in the order they should be searched, starting with `XDG_DATA_HOME` and
+/// followed by `XDG_DATA_DIRS`
+pub fn data_dirs() -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = BaseDirs::new()
+        .map(|base| base.data_dir().to_path_buf())
+        .into_iter()
+        .collect();
+    dirs.extend(split_paths("XDG_DATA_DIRS", DEFAULT_DATA_DIRS));
+    dirs
+}
+
+/// The system config directories from `XDG_CONFIG_DIRS`, the most important one first
+pub fn config_dirs() -> Vec<PathBuf> {
+    split_paths("XDG_CONFIG_DIRS", DEFAULT_CONFIG_DIRS)
+}
+
+/// The directory opener keeps its cache in, `$XDG_CACHE_HOME/opener`
+pub fn cache_dir() -> Result<PathBuf> {
+    let base = BaseDirs::new().ok_or_else(|| anyhow!("Failed to find the cache directory"))?;
+    Ok(base.cache_dir().join("opener"))
+}
+
+/// Splits a colon separated list of absolute paths from an environment variable, using the default
+/// if the variable is not set or empty. Relative paths are ignored like the spec says.
+fn split_paths(var: &str, default: &str) -> Vec<PathBuf> {
+    let value = env::var(var)
+        .ok()
+        .filter(|value| !value.is_empty())
+        .unwrap_or_else(|| default.to_string());
+    value
+        .split(':')
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .collect()
+}